@@ -0,0 +1,96 @@
+"""Python bindings for the DNA palindrome finder and adapter aligner"""
+from dataclasses import astuple, dataclass
+from os import fspath
+from typing import Iterator, List, Optional
+
+from ._palindromes import AdapterAligner, Finder
+from .stats import filter_overlap, filter_repeats, longest, num_bps
+
+__all__ = [
+    "Palindrome", "AdapterHit", "Adapters", "wfa", "exact_match", "search_file", "to_dataframe",
+    "num_bps", "longest", "filter_repeats", "filter_overlap",
+]
+
+#Column names of the output file, in the order of the Palindrome fields
+COLUMNS = ["Start", "End", "Arm-Length", "Gap", "Length", "Mismatches", "Strand", "Type", "Seq-name", "Sequence", "Arm-Quality"]
+
+
+@dataclass(frozen=True)
+class Palindrome:
+    start: int
+    end: int
+    arm_length: int
+    gap: int
+    length: int
+    mismatches: int
+    strand: str
+    type: str
+    seq_name: str
+    sequence: str
+    arm_quality: Optional[float] = None
+
+
+@dataclass(frozen=True)
+class AdapterHit:
+    adapter: str
+    start: int
+    end: int
+    score: int
+    orientation: str
+    cigar: str
+
+
+def _finder(algorithm, settings):
+    if algorithm == "wfa":
+        return Finder.wfa(**settings)
+    if algorithm == "exact-match":
+        return Finder.exact_match(**settings)
+    raise ValueError(f"Unknown algorithm {algorithm}, expected wfa or exact-match")
+
+
+def wfa(seq, **settings) -> List[Palindrome]:
+    """Finds palindromes in a str or bytes sequence with the WFA algorithm. Settings take the same names as WfaConfig"""
+    return [Palindrome(*row) for row in Finder.wfa(**settings).search(seq)]
+
+
+def exact_match(seq, **settings) -> List[Palindrome]:
+    """Finds palindromes in a str or bytes sequence with the fixed-mismatch algorithm"""
+    return [Palindrome(*row) for row in Finder.exact_match(**settings).search(seq)]
+
+
+def search_file(path, algorithm="wfa", fastq=None, gzip=None, **settings) -> Iterator[Palindrome]:
+    """Streams the palindromes in a FASTA or FASTQ file one record at a time.
+    The format is taken from the file extension unless fastq or gzip are given"""
+    path = fspath(path)
+    name = path.lower()
+    if gzip is None:
+        gzip = name.endswith(".gz")
+    if fastq is None:
+        fastq = name.removesuffix(".gz").endswith((".fq", ".fastq"))
+
+    for _, rows in _finder(algorithm, settings).search_file(path, fastq, gzip):
+        for row in rows:
+            yield Palindrome(*row)
+
+
+def to_dataframe(palindromes):
+    """Converts palindromes to a pandas DataFrame with the same columns as the output file"""
+    import pandas
+
+    return pandas.DataFrame([astuple(palin) for palin in palindromes], columns=COLUMNS)
+
+
+class Adapters:
+    """Adapters from a FASTA file, searched for in both orientations.
+    Scoring takes preset, match_score, mismatch_score, gap_open and gap_extend, the same as the command line"""
+
+    def __init__(self, adapters_file, longest_adapter=None, **scoring):
+        self._aligner = AdapterAligner(fspath(adapters_file), longest_adapter=longest_adapter, **scoring)
+
+    @property
+    def names(self) -> List[str]:
+        return self._aligner.names()
+
+    def align(self, seq, score_cutoff) -> List[AdapterHit]:
+        """Alignments scoring at least score_cutoff, with 0-based positions in the read and an exclusive end"""
+        return [AdapterHit(*row) for row in self._aligner.align(seq, score_cutoff)]
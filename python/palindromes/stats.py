@@ -0,0 +1,55 @@
+"""Statistics and filters for palindromes in a DataFrame, either from to_dataframe or an output file read with pandas"""
+import sys
+
+
+def num_bps(df, start_pt=0, end_pt=sys.maxsize):
+    """Number of bases in palindromes from start_pt up to end_pt, counting bases in overlapping palindromes once"""
+    total = 0
+    for _, group in df.groupby("Seq-name", sort=False):
+        #First base not counted yet
+        index = start_pt
+        for start, end in sorted(zip(group["Start"], group["End"])):
+            start = max(start, index)
+            end = min(end, end_pt - 1)
+            if end >= start:
+                total += end - start + 1
+                index = end + 1
+    return total
+
+
+def longest(df):
+    """Row of the longest palindrome"""
+    return df.loc[df["Length"].idxmax()]
+
+
+def filter_repeats(df, ratio):
+    """Keeps palindromes where at least ratio of each arm is uppercase, dropping those in soft-masked repeats"""
+    keep = [
+        has_more_uppercase(seq[:arm], ratio) and has_more_uppercase(seq[-arm:], ratio)
+        for seq, arm in zip(df["Sequence"], df["Arm-Length"])
+    ]
+    return df[keep]
+
+
+def has_more_uppercase(sequence, ratio):
+    if len(sequence) == 0:
+        return True
+    return sum(1 for char in sequence if char.isupper()) / len(sequence) >= ratio
+
+
+def filter_overlap(df, ratio):
+    """Drops palindromes overlapping the last one kept by more than ratio of the shorter palindrome"""
+    keep = []
+    prev = None
+    for name, start, end in zip(df["Seq-name"], df["Start"], df["End"]):
+        if prev is not None and prev[0] == name and calculate_overlap(prev[1], prev[2], start, end) > ratio:
+            keep.append(False)
+            continue
+        keep.append(True)
+        prev = (name, start, end)
+    return df[keep]
+
+
+def calculate_overlap(start1, end1, start2, end2):
+    overlap = max(0, min(end1, end2) - max(start1, start2) + 1)
+    return overlap / min(end1 - start1 + 1, end2 - start2 + 1)
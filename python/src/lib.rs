@@ -0,0 +1,220 @@
+use palindromes::{
+    adapters::{find_adapters, AdapterSet, AlignScoring},
+    fasta_parsing::open_records,
+    finder::ConfigScratch,
+    Config, Fasta, FixedConfig, HeaderSplit, Palindrome, PalindromeFinder, Preset, ScoringConfig, WfaConfig,
+};
+use pyo3::{exceptions::PyValueError, prelude::*, types::PyBytes};
+
+//A palindrome with the same columns as the output file
+type PalindromeRow = (u32, u32, u32, u32, u32, u32, String, String, String, String, Option<f32>);
+
+//Adapter, start, end, score, orientation and CIGAR of an adapter alignment
+type AdapterRow = (String, usize, usize, i32, String, String);
+
+//Sequences can be given as str or bytes
+#[derive(FromPyObject)]
+enum Bases<'py> {
+    Text(String),
+    Bytes(Bound<'py, PyBytes>),
+}
+
+impl Bases<'_> {
+    fn into_fasta(self, name: String) -> Fasta {
+        let seq = match self {
+            Bases::Text(text) => text.into_bytes(),
+            Bases::Bytes(bytes) => bytes.as_bytes().to_vec(),
+        };
+        Fasta::new(name, seq.into(), None)
+    }
+}
+
+fn to_row(palin: Palindrome) -> PalindromeRow {
+    (
+        palin.get_start(),
+        palin.get_end(),
+        palin.get_arm_length(),
+        palin.get_gap(),
+        palin.get_length(),
+        palin.get_mismatches(),
+        palin.get_strand().to_string(),
+        palin.get_repeat_type().to_string(),
+        palin.get_seq_name().to_owned(),
+        String::from_utf8_lossy(palin.get_sequence()).into_owned(),
+        palin.get_arm_quality(),
+    )
+}
+
+//Searches one record, with arm qualities for reads that have them the same as the output file
+fn search_record(config: &Config, scratch: &mut ConfigScratch, fasta: &Fasta) -> PyResult<Vec<PalindromeRow>> {
+    let mut palins = Vec::new();
+    config.find(fasta, &mut palins, scratch)?;
+    if let Some(quality) = fasta.get_quality() {
+        for palin in &mut palins {
+            palin.set_arm_quality(quality);
+        }
+    }
+    Ok(palins.into_iter().map(to_row).collect())
+}
+
+//Search settings along with the buffers reused between searches
+#[pyclass(module = "palindromes._palindromes")]
+struct Finder {
+    config: Config,
+    scratch: ConfigScratch,
+}
+
+impl Finder {
+    fn new(config: Config) -> PyResult<Self> {
+        let scratch = config.scratch()?;
+        Ok(Self { config, scratch })
+    }
+}
+
+//Settings left as None take the command line defaults
+#[pymethods]
+impl Finder {
+    #[staticmethod]
+    #[pyo3(signature = (*, min_length=None, gap_len=None, match_bonus=None, mismatch_penalty=None, x_drop=None, mismatch_proportion=None, both_strands=None))]
+    fn wfa(
+        min_length: Option<usize>,
+        gap_len: Option<usize>,
+        match_bonus: Option<f32>,
+        mismatch_penalty: Option<f32>,
+        x_drop: Option<f32>,
+        mismatch_proportion: Option<f32>,
+        both_strands: Option<bool>,
+    ) -> PyResult<Self> {
+        let default = WfaConfig::default();
+        Self::new(Config::Wfa(WfaConfig {
+            min_length: min_length.unwrap_or(default.min_length),
+            gap_len: gap_len.unwrap_or(default.gap_len),
+            match_bonus: match_bonus.unwrap_or(default.match_bonus),
+            mismatch_penalty: mismatch_penalty.unwrap_or(default.mismatch_penalty),
+            x_drop: x_drop.unwrap_or(default.x_drop),
+            mismatch_proportion: mismatch_proportion.unwrap_or(default.mismatch_proportion),
+            both_strands: both_strands.unwrap_or(default.both_strands),
+        }))
+    }
+
+    #[staticmethod]
+    #[pyo3(signature = (*, min_length=None, gap_len=None, mismatches=None))]
+    fn exact_match(min_length: Option<usize>, gap_len: Option<usize>, mismatches: Option<u32>) -> PyResult<Self> {
+        let default = FixedConfig::default();
+        Self::new(Config::ExactMatch(FixedConfig {
+            min_length: min_length.unwrap_or(default.min_length),
+            gap_len: gap_len.unwrap_or(default.gap_len),
+            mismatches: mismatches.unwrap_or(default.mismatches),
+        }))
+    }
+
+    #[pyo3(signature = (seq, name=String::new()))]
+    fn search(&mut self, seq: Bases, name: String) -> PyResult<Vec<PalindromeRow>> {
+        search_record(&self.config, &mut self.scratch, &seq.into_fasta(name))
+    }
+
+    //Reads the file one record at a time, yielding the ID and palindromes of each
+    #[pyo3(signature = (path, fastq=false, gzip=false))]
+    fn search_file(&self, path: &str, fastq: bool, gzip: bool) -> PyResult<RecordSearch> {
+        Ok(RecordSearch {
+            records: open_records(path, fastq, gzip, HeaderSplit::default())?,
+            config: self.config.clone(),
+            scratch: self.config.scratch()?,
+        })
+    }
+}
+
+//The file readers aren't Send, so the iterator stays on the thread that made it
+#[pyclass(unsendable, module = "palindromes._palindromes")]
+struct RecordSearch {
+    records: Box<dyn Iterator<Item = anyhow::Result<Fasta>>>,
+    config: Config,
+    scratch: ConfigScratch,
+}
+
+#[pymethods]
+impl RecordSearch {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self) -> PyResult<Option<(String, Vec<PalindromeRow>)>> {
+        let Some(fasta) = self.records.next() else {
+            return Ok(None);
+        };
+        let fasta = fasta?;
+        let palins = search_record(&self.config, &mut self.scratch, &fasta)?;
+        Ok(Some((fasta.get_id().to_owned(), palins)))
+    }
+}
+
+//Adapters loaded from a FASTA file, aligned with the same scoring as the adapters mode
+#[pyclass(module = "palindromes._palindromes")]
+struct AdapterAligner {
+    //Boxed since the aligner's SIMD types need more alignment than Python gives its objects
+    adapters: Box<AdapterSet>,
+}
+
+#[pymethods]
+impl AdapterAligner {
+    #[new]
+    #[pyo3(signature = (adapters_file, *, preset=None, match_score=None, mismatch_score=None, gap_open=None, gap_extend=None, longest_adapter=None))]
+    fn new(
+        adapters_file: &str,
+        preset: Option<&str>,
+        match_score: Option<i8>,
+        mismatch_score: Option<i8>,
+        gap_open: Option<i8>,
+        gap_extend: Option<i8>,
+        longest_adapter: Option<usize>,
+    ) -> PyResult<Self> {
+        let preset = match preset {
+            None => None,
+            Some("r9.4") => Some(Preset::R94),
+            Some("r10.4") => Some(Preset::R104),
+            Some("hifi") => Some(Preset::Hifi),
+            Some(preset) => return Err(PyValueError::new_err(format!("Unknown preset {}, expected r9.4, r10.4 or hifi", preset))),
+        };
+        let scoring = AlignScoring::new(&ScoringConfig {
+            preset,
+            match_score,
+            mismatch_score,
+            gap_open,
+            gap_extend,
+            ..Default::default()
+        })?;
+        Ok(Self { adapters: Box::new(AdapterSet::from_file(adapters_file, longest_adapter, scoring)?) })
+    }
+
+    fn names(&self) -> Vec<String> {
+        self.adapters.names().to_vec()
+    }
+
+    //Alignments of each adapter in either orientation scoring at least score_cutoff, with 0-based positions and an exclusive end
+    fn align(&self, seq: Bases, score_cutoff: i32) -> PyResult<Vec<AdapterRow>> {
+        let fasta = seq.into_fasta(String::new());
+        let mut hits = Vec::new();
+        find_adapters(&fasta, &self.adapters, score_cutoff, 0, &mut hits)?;
+        Ok(hits
+            .into_iter()
+            .map(|hit| {
+                (
+                    hit.get_ref().to_owned(),
+                    hit.get_ref_start(),
+                    hit.get_ref_end(),
+                    hit.get_result().score,
+                    hit.get_orientation().to_string(),
+                    hit.get_seq().to_owned(),
+                )
+            })
+            .collect())
+    }
+}
+
+#[pymodule]
+fn _palindromes(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Finder>()?;
+    m.add_class::<RecordSearch>()?;
+    m.add_class::<AdapterAligner>()?;
+    Ok(())
+}
@@ -0,0 +1,98 @@
+"""Tests for the Python bindings, run with python -m unittest discover python/tests once the package is installed"""
+import os
+import tempfile
+import unittest
+
+import palindromes
+
+try:
+    import pandas
+except ImportError:
+    pandas = None
+
+#Arms of ACGGTCATTGCA and TGCAATGACCGT around a GAT spacer
+PALINDROME = "ACGGTCATTGCAGATTGCAATGACCGT"
+SEQ = "GGGGGGGG" + PALINDROME + "GGGGGGGG"
+ADAPTER = "AATGTACTTCGTTCAGTTACGTATTGCT"
+INSERT = "CAGTCGATCGGCTTAGCATCGGATCTAGCAGTTCGACGATCGATGCTAGCTAGGCTAATCGGATCGATCGTAGCTAGCTGACT"
+
+
+def reverse_complement(seq):
+    return seq[::-1].translate(str.maketrans("ACGT", "TGCA"))
+
+
+class TempFiles(unittest.TestCase):
+    def setUp(self):
+        self.dir = tempfile.TemporaryDirectory()
+
+    def tearDown(self):
+        self.dir.cleanup()
+
+    def write(self, name, text):
+        path = os.path.join(self.dir.name, name)
+        with open(path, "w") as file:
+            file.write(text)
+        return path
+
+
+class SearchTests(TempFiles):
+    def test_finds_palindrome_in_str_and_bytes(self):
+        palins = palindromes.wfa(SEQ, min_length=10)
+        self.assertEqual(palins, palindromes.wfa(SEQ.encode(), min_length=10))
+        self.assertEqual(len(palins), 1)
+        palin = palins[0]
+        #The arms extend over one mismatch into the flanks
+        self.assertEqual((palin.start, palin.end, palin.arm_length, palin.gap, palin.mismatches), (7, 35, 13, 3, 1))
+        self.assertEqual((palin.strand, palin.type, palin.sequence), ("+", "inverted", SEQ[7:36]))
+        self.assertIsNone(palin.arm_quality)
+
+    def test_exact_match_takes_its_own_settings(self):
+        palins = palindromes.exact_match(SEQ, min_length=12, gap_len=3, mismatches=0)
+        self.assertEqual([(palin.start, palin.end) for palin in palins], [(8, 34)])
+        with self.assertRaises(TypeError):
+            palindromes.exact_match(SEQ, x_drop=10)
+
+    def test_search_file_reads_fasta_and_fastq(self):
+        fasta = self.write("seqs.fa", f">first desc\n{SEQ[:20]}\n{SEQ[20:]}\n>second\n{INSERT}\n")
+        palins = list(palindromes.search_file(fasta, min_length=10))
+        self.assertEqual([(palin.seq_name, palin.start) for palin in palins], [("first", 7)])
+
+        fastq = self.write("reads.fastq", f"@read\n{SEQ}\n+\n{'I' * len(SEQ)}\n")
+        palins = list(palindromes.search_file(fastq, min_length=10))
+        self.assertEqual(len(palins), 1)
+        self.assertEqual(palins[0].arm_quality, 40.0)
+
+    def test_unknown_algorithm(self):
+        with self.assertRaises(ValueError):
+            list(palindromes.search_file("missing.fa", algorithm="mirror"))
+
+
+class AdapterTests(TempFiles):
+    def test_aligns_adapters_in_both_orientations(self):
+        adapters = palindromes.Adapters(self.write("adapters.fa", f">first\n{ADAPTER}\n"))
+        self.assertEqual(adapters.names, ["first"])
+
+        read = INSERT + reverse_complement(ADAPTER) + INSERT
+        hits = adapters.align(read, score_cutoff=20)
+        self.assertEqual(len(hits), 1)
+        hit = hits[0]
+        self.assertEqual((hit.adapter, hit.start, hit.end), ("first", len(INSERT), len(INSERT) + len(ADAPTER)))
+        self.assertEqual((hit.score, hit.orientation), (len(ADAPTER), "-"))
+
+    def test_rejects_unknown_preset(self):
+        with self.assertRaises(ValueError):
+            palindromes.Adapters(self.write("adapters.fa", f">first\n{ADAPTER}\n"), preset="r8")
+
+
+@unittest.skipIf(pandas is None, "pandas is not installed")
+class StatsTests(unittest.TestCase):
+    def test_dataframe_statistics(self):
+        df = palindromes.to_dataframe(palindromes.wfa(SEQ, min_length=10))
+        self.assertEqual(list(df.columns), palindromes.COLUMNS)
+        self.assertEqual(palindromes.num_bps(df), len(PALINDROME) + 2)
+        self.assertEqual(palindromes.longest(df)["Start"], 7)
+        self.assertEqual(len(palindromes.filter_repeats(df, 0.9)), 1)
+
+
+if __name__ == "__main__":
+    unittest.main()
@@ -1,26 +1,37 @@
-use std::{cmp::min, fs::File, io::BufReader};
+use std::{cmp::{max, min}, fmt::Display, fs::File, io::BufReader, ops::Range};
 
-use anyhow::{anyhow, bail, Ok, Result};
+use anyhow::{ensure, Ok, Result};
 use block_aligner::{
-    cigar::Cigar, percent_len, scan_block::{AlignResult, Block, PaddedBytes}, scores::{Gaps, NucMatrix, NW1}
+    cigar::{Cigar, Operation}, percent_len, scan_block::{AlignResult, Block, PaddedBytes}, scores::{Gaps, Matrix, NucMatrix}
 };
 
-use crate::{command_line::AdapterArgs, fasta_parsing::{Fasta, FastaIterator}};
+use crate::{config::{AdapterConfig, ClassifyConfig, Preset, ScoringConfig, TrimConfig}, fasta_parsing::{reverse_complement, Fasta, FastaIterator}, output::Strand};
+
+//Replaces adapters already found in a read before searching it again. It scores as a mismatch against everything
+//including itself, unlike N which matches N in adapters with placeholder bases. The matrix is indexed by the low bits
+//of each byte, and X doesn't share them with the bases or the Z used for padding
+const MASK: u8 = b'X';
 
 pub struct Adapter {
-    pub name: String,
-    pub ref_name: String,
-    pub alignment: String,
-    pub result: AlignResult,
+    name: String,
+    ref_name: String,
+    alignment: String,
+    result: AlignResult,
+    //Start of the alignment in the read, where result.reference_idx is the end
+    ref_start: usize,
+    //Reverse if the reverse complement of the adapter was found
+    orientation: Strand,
 }
 
 impl Adapter {
-    pub fn new(name: String, ref_name: String, alignment: String, result: AlignResult) -> Self {
+    pub(crate) fn new(name: String, ref_name: String, alignment: String, result: AlignResult, ref_start: usize, orientation: Strand) -> Self {
         Self {
             name,
             ref_name,
             alignment,
-            result
+            result,
+            ref_start,
+            orientation
         }
     }
     pub fn get_name(&self) -> &str {
@@ -35,73 +46,425 @@ impl Adapter {
     pub fn get_result(&self) -> &AlignResult{
         &self.result
     }
+    pub fn get_ref_start(&self) -> usize {
+        self.ref_start
+    }
+    pub fn get_ref_end(&self) -> usize {
+        self.result.reference_idx
+    }
+    pub fn get_orientation(&self) -> Strand {
+        self.orientation
+    }
 }
 
-//Aligns adapter sequence against ref sequence
-pub fn align_adapters(fasta: &mut Fasta, cmds: &AdapterArgs, output: &mut Vec<Adapter>) -> Result<()> {
-    
-    let gaps = Gaps {
-        open: -2,
-        extend: -1,
-    };
+//Where adapters were found in a read, where an adapter away from the ends outranks the others
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadClass {
+    Clean,
+    FivePrime,
+    ThreePrime,
+    BothEnds,
+    Internal,
+    PolyTTrimmed,
+}
+
+impl ReadClass {
+    pub const ALL: [ReadClass; 6] = [
+        ReadClass::Clean,
+        ReadClass::FivePrime,
+        ReadClass::ThreePrime,
+        ReadClass::BothEnds,
+        ReadClass::Internal,
+        ReadClass::PolyTTrimmed,
+    ];
+}
+
+impl Display for ReadClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReadClass::Clean => write!(f, "clean"),
+            ReadClass::FivePrime => write!(f, "5-prime"),
+            ReadClass::ThreePrime => write!(f, "3-prime"),
+            ReadClass::BothEnds => write!(f, "both-ends"),
+            ReadClass::Internal => write!(f, "internal"),
+            ReadClass::PolyTTrimmed => write!(f, "poly-t-trimmed"),
+        }
+    }
+}
+
+pub struct ReadSummary {
+    pub read: String,
+    //Length before poly-T removal
+    pub length: usize,
+    pub class: ReadClass,
+    //Names of the adapters found, each listed once
+    pub adapters: Vec<String>,
+}
+
+//Scores for the aligner, taken from the preset with any options given replacing them
+pub struct AlignScoring {
+    matrix: NucMatrix,
+    gaps: Gaps,
+    min_block_size: Option<usize>,
+    max_block_size: Option<usize>,
+    x_drop: Option<i32>,
+}
+
+impl AlignScoring {
+    pub fn new(args: &ScoringConfig) -> Result<Self> {
+        //Match, mismatch, gap open and gap extend
+        let (match_score, mismatch_score, gap_open, gap_extend) = match args.preset {
+            None => (1, -1, -2, -1),
+            Some(Preset::R94) => (2, -2, -3, -1),
+            Some(Preset::R104) => (2, -3, -4, -2),
+            Some(Preset::Hifi) => (2, -6, -8, -4),
+        };
+        let match_score = args.match_score.unwrap_or(match_score);
+        let mismatch_score = args.mismatch_score.unwrap_or(mismatch_score);
+        let gap_open = args.gap_open.unwrap_or(gap_open);
+        let gap_extend = args.gap_extend.unwrap_or(gap_extend);
+
+        ensure!(match_score > 0, "Match score must be positive");
+        ensure!(mismatch_score < 0, "Mismatch score must be negative");
+        ensure!(gap_extend < 0 && gap_open < gap_extend, "Gap scores must be negative, with the open score lower than the extend score");
+        ensure!(args.x_drop.is_none_or(|x_drop| x_drop >= 0), "X-drop can't be negative");
+
+        let mut matrix = NucMatrix::new_simple(match_score, mismatch_score);
+        for base in [b'A', b'C', b'G', b'T', b'N', MASK] {
+            matrix.set(MASK, base, mismatch_score);
+        }
+
+        Ok(Self {
+            matrix,
+            gaps: Gaps { open: gap_open, extend: gap_extend },
+            min_block_size: args.min_block_size,
+            max_block_size: args.max_block_size,
+            x_drop: args.x_drop,
+        })
+    }
+
+    //Block sizes from the options, or fitting a query of query_len if not given
+    fn block_sizes(&self, query_len: usize) -> Result<(usize, usize)> {
+        //The block has to be longer than the query for free end gaps
+        let min_block_size = self.min_block_size.unwrap_or_else(|| percent_len(query_len + 1, 1.0));
+        let max_block_size = self.max_block_size.unwrap_or(4*min_block_size);
+
+        ensure!(min_block_size.is_power_of_two() && max_block_size.is_power_of_two(), "Block sizes must be powers of two");
+        ensure!(min_block_size <= max_block_size, "Min block size can't be larger than the max block size");
+        ensure!(max_block_size < u16::MAX as usize, "Block sizes must be smaller than {}", u16::MAX);
+        ensure!(min_block_size > query_len, "Min block size must be larger than the {} bp sequence aligned against the reads", query_len);
+        Ok((min_block_size, max_block_size))
+    }
+
+    //Block sizes for the poly-T, which is longer than most adapters, so the options are only raised to fit it
+    fn poly_t_block_sizes(&self, query_len: usize) -> (usize, usize) {
+        let fitted = percent_len(query_len + 1, 1.0);
+        let min_block_size = max(self.min_block_size.unwrap_or(fitted), fitted);
+        let max_block_size = max(self.max_block_size.unwrap_or(4*min_block_size), min_block_size);
+        (min_block_size, max_block_size)
+    }
+}
+
+//Adapter sequence padded for the aligner
+struct AdapterProfile {
+    name: String,
+    padded: PaddedBytes,
+    orientation: Strand,
+    //Position of the adapter in the file
+    index: usize,
+}
+
+//Adapters loaded once from the adapter file, with block sizes fitting the longest one
+pub struct AdapterSet {
+    adapters: Vec<AdapterProfile>,
+    names: Vec<String>,
+    scoring: AlignScoring,
+    min_block_size: usize,
+    max_block_size: usize,
+}
+
+impl AdapterSet {
+    pub fn load(cmds: &AdapterConfig) -> Result<Self> {
+        Self::from_file(&cmds.adapters_file_path, cmds.longest_adapter, AlignScoring::new(&cmds.scoring)?)
+    }
+
+    pub fn from_file(file_name: &str, longest_adapter: Option<usize>, scoring: AlignScoring) -> Result<Self> {
+        let adapter_file = File::open(file_name)?;
+        let mut sequences = Vec::new();
+        for fasta in FastaIterator::new(BufReader::new(adapter_file)) {
+            let fasta = fasta?;
+            let seq = fasta.get_sequence().to_contiguous().into_owned();
+            sequences.push((fasta.get_name().to_owned(), seq));
+        }
+        ensure!(!sequences.is_empty(), "No adapters found in {}", file_name);
+        Self::new(sequences, longest_adapter, scoring)
+    }
 
+    //Builds the set from the name and bases of each adapter
+    pub fn new(sequences: Vec<(String, Vec<u8>)>, longest_adapter: Option<usize>, scoring: AlignScoring) -> Result<Self> {
+        ensure!(!sequences.is_empty(), "No adapters given");
+        let longest = sequences.iter().map(|(_, seq)| seq.len()).max().unwrap_or(0);
+        let longest = match longest_adapter {
+            Some(longest_adapter) => {
+                ensure!(longest <= longest_adapter, "Largest adapter length not large enough");
+                longest_adapter
+            }
+            None => longest,
+        };
+
+        let (min_block_size, max_block_size) = scoring.block_sizes(longest)?;
+
+        //Adapters can be ligated in either orientation, so each one is also searched as its reverse complement
+        let mut adapters = Vec::new();
+        let mut names = Vec::new();
+        for (index, (name, seq)) in sequences.into_iter().enumerate() {
+            let reverse = reverse_complement(&seq)?;
+            adapters.push(AdapterProfile {
+                name: name.clone(),
+                padded: PaddedBytes::from_bytes::<NucMatrix>(&seq, max_block_size),
+                orientation: Strand::Forward,
+                index,
+            });
+            //Skips adapters that are their own reverse complement
+            if reverse != seq {
+                adapters.push(AdapterProfile {
+                    name: name.clone(),
+                    padded: PaddedBytes::from_bytes::<NucMatrix>(&reverse, max_block_size),
+                    orientation: Strand::Reverse,
+                    index,
+                });
+            }
+            names.push(name);
+        }
+        Ok(Self { adapters, names, scoring, min_block_size, max_block_size })
+    }
+
+    //Names of the adapters in the order of the file
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    //Best alignment score of every adapter against seq in either orientation, in the order of names()
+    pub fn scores(&self, seq: &[u8]) -> Vec<i32> {
+        let read_padded = PaddedBytes::from_bytes::<NucMatrix>(seq, self.max_block_size);
+        let mut block = Block::<false, false, false, true, true>::new(self.min_block_size, seq.len(), self.max_block_size);
+
+        let mut scores = vec![i32::MIN; self.names.len()];
+        for adapter in &self.adapters {
+            block.align(
+                &adapter.padded,
+                &read_padded,
+                &self.scoring.matrix,
+                self.scoring.gaps,
+                self.min_block_size..=self.max_block_size,
+                0,
+            );
+            scores[adapter.index] = max(scores[adapter.index], block.res().score);
+        }
+        scores
+    }
+}
+
+//Aligns adapter sequence against ref sequence
+pub fn align_adapters(fasta: &mut Fasta, adapter_set: &AdapterSet, cmds: &AdapterConfig, output: &mut Vec<Adapter>) -> Result<()> {
     let mut offset = 0;
 
     if cmds.remove_t{
-        let t_res = remove_t(fasta, cmds.score_cutoff)?;
-        offset = match t_res {
-            Some(t_res) => t_res.reference_idx,
+        let kept = remove_t(fasta, &adapter_set.scoring, cmds)?;
+        offset = match kept {
+            Some(kept) => kept.start,
             None => 0
         };
     }
-    
-    let seq = fasta.get_sequence();
-    let len = seq.len();
+    find_adapters(fasta, adapter_set, cmds.score_cutoff, offset, output)
+}
+
+//Trims adapters found within end_size bases of either end of the read, along with poly-T if enabled.
+//Adapters further inside the read are left in place
+pub fn trim_adapters(fasta: &mut Fasta, adapter_set: &AdapterSet, cmds: &TrimConfig, hits: &mut Vec<Adapter>) -> Result<()> {
+    find_read_adapters(fasta, adapter_set, &cmds.adapters, hits)?;
+    find_repeated_adapters(fasta, adapter_set, cmds.adapters.score_cutoff, hits)?;
+    let range = trim_range(hits, fasta.get_sequence().len(), cmds.end_size);
+    fasta.trim(range);
+    Ok(())
+}
 
-    let min_block_size: usize = percent_len(cmds.longest_adapter, 1.0);
-    let max_block_size: usize = 4*min_block_size;
+//Trims the read like trim_adapters and then cuts it at the adapters left inside it,
+//adding the fragments to output with _1, _2... appended to the read ID
+pub fn split_adapters(mut fasta: Fasta, adapter_set: &AdapterSet, cmds: &TrimConfig, hits: &mut Vec<Adapter>, output: &mut Vec<Fasta>) -> Result<()> {
+    find_read_adapters(&mut fasta, adapter_set, &cmds.adapters, hits)?;
+    find_repeated_adapters(&fasta, adapter_set, cmds.adapters.score_cutoff, hits)?;
+    let len = fasta.get_sequence().len();
+    let range = trim_range(hits, len, cmds.end_size);
+
+    let mut cuts: Vec<(usize, usize)> = hits
+        .iter()
+        .filter(|hit| !is_end_hit(hit, len, cmds.end_size))
+        .map(|hit| (hit.get_ref_start(), hit.get_ref_end()))
+        .filter(|&(start, end)| start < range.end && end > range.start)
+        .collect();
+    if cuts.is_empty() {
+        fasta.trim(range);
+        output.push(fasta);
+        return Ok(());
+    }
+    cuts.sort_unstable();
+
+    //Overlapping adapters are cut out as one
+    let mut fragments = Vec::new();
+    let mut start = range.start;
+    for (cut_start, cut_end) in cuts {
+        if cut_start > start {
+            fragments.push(start..cut_start);
+        }
+        start = max(start, cut_end);
+    }
+    if start < range.end {
+        fragments.push(start..range.end);
+    }
 
-    let adapter_file = File::open(&cmds.adapters_file_path)?;
+    let fragments = fragments
+        .into_iter()
+        .filter(|fragment| fragment.len() >= cmds.min_length);
+    for (i, fragment) in fragments.enumerate() {
+        let mut read = fasta.clone();
+        read.trim(fragment);
+        read.set_id(&format!("{}_{}", fasta.get_id(), i + 1));
+        output.push(read);
+    }
+    Ok(())
+}
 
-    let iterator = FastaIterator::new(BufReader::new(adapter_file));
+//Classifies the read by where adapters are found in it, once poly-T is removed if enabled
+pub fn classify_read(fasta: &mut Fasta, adapter_set: &AdapterSet, cmds: &ClassifyConfig, hits: &mut Vec<Adapter>) -> Result<ReadSummary> {
+    let length = fasta.get_sequence().len();
+    let tail_removed = find_read_adapters(fasta, adapter_set, &cmds.adapters, hits)?;
+    find_repeated_adapters(fasta, adapter_set, cmds.adapters.score_cutoff, hits)?;
+
+    let len = fasta.get_sequence().len();
+    let (mut five_prime, mut three_prime, mut internal) = (false, false, false);
+    for hit in hits.iter() {
+        if !is_end_hit(hit, len, cmds.end_size) {
+            internal = true;
+        } else if hit.get_ref_start() < cmds.end_size {
+            five_prime = true;
+        } else {
+            three_prime = true;
+        }
+    }
+    let class = match (internal, five_prime, three_prime) {
+        (true, _, _) => ReadClass::Internal,
+        (false, true, true) => ReadClass::BothEnds,
+        (false, true, false) => ReadClass::FivePrime,
+        (false, false, true) => ReadClass::ThreePrime,
+        (false, false, false) if tail_removed => ReadClass::PolyTTrimmed,
+        (false, false, false) => ReadClass::Clean,
+    };
 
-    let read_padded = PaddedBytes::from_str::<NucMatrix>(seq, max_block_size);
+    let mut adapters: Vec<String> = hits.iter().map(|hit| hit.get_ref().to_owned()).collect();
+    adapters.sort_unstable();
+    adapters.dedup();
 
-    let mut local = Block::<true, false, false, true, true>::new(min_block_size, len, max_block_size);
+    Ok(ReadSummary {
+        read: fasta.get_id().to_owned(),
+        length,
+        class,
+        adapters,
+    })
+}
 
-    for line in iterator {
-        let line = line?;
-        
-        let adapter = line.get_sequence();
+//Removes poly-T if enabled and finds the best hit of every adapter in what's left of the read.
+//Returns whether a tail was removed
+fn find_read_adapters(fasta: &mut Fasta, adapter_set: &AdapterSet, cmds: &AdapterConfig, hits: &mut Vec<Adapter>) -> Result<bool> {
+    let mut tail_removed = false;
+    if cmds.remove_t {
+        tail_removed = remove_t(fasta, &adapter_set.scoring, cmds)?.is_some();
+    }
+    hits.clear();
+    find_adapters(fasta, adapter_set, cmds.score_cutoff, 0, hits)?;
+    Ok(tail_removed)
+}
 
-        if cmds.longest_adapter < adapter.len(){
-            return Err(anyhow!("Largest adapter length not large enough"));
+//Searches again with the hits found so far masked out, since each search only finds the best hit of every adapter
+fn find_repeated_adapters(fasta: &Fasta, adapter_set: &AdapterSet, score_cutoff: i32, hits: &mut Vec<Adapter>) -> Result<()> {
+    let mut masked = fasta.clone();
+    let mut searched = 0;
+    let mut found = Vec::new();
+    while searched < hits.len() {
+        let seq = masked.bases_mut();
+        for hit in &hits[searched..] {
+            seq[hit.get_ref_start()..hit.get_ref_end()].fill(MASK);
         }
+        searched = hits.len();
+        find_adapters(&masked, adapter_set, score_cutoff, 0, &mut found)?;
+
+        //Hits already reported are dropped, so the search ends once a pass finds nothing new
+        found.retain(|hit| {
+            !hits.iter().any(|old| {
+                (old.get_ref_start(), old.get_ref_end(), old.get_orientation()) == (hit.get_ref_start(), hit.get_ref_end(), hit.get_orientation())
+            })
+        });
+        hits.append(&mut found);
+    }
+    Ok(())
+}
 
+//Range of the read left after cutting off the adapters found at either end
+fn trim_range(hits: &[Adapter], len: usize, end_size: usize) -> Range<usize> {
+    let mut start = 0;
+    let mut end = len;
+    for hit in hits.iter().filter(|hit| is_end_hit(hit, len, end_size)) {
+        if hit.get_ref_start() < end_size {
+            start = max(start, hit.get_ref_end());
+        } else {
+            end = min(end, hit.get_ref_start());
+        }
+    }
+    start..max(start, end)
+}
+
+fn is_end_hit(hit: &Adapter, len: usize, end_size: usize) -> bool {
+    hit.get_ref_start() < end_size || hit.get_ref_end() + end_size > len
+}
+
+//Aligns every adapter against the read, with offset added to the reported end positions
+pub fn find_adapters(fasta: &Fasta, adapter_set: &AdapterSet, score_cutoff: i32, offset: usize, output: &mut Vec<Adapter>) -> Result<()> {
+    let seq = fasta.get_sequence().to_contiguous();
+    let len = seq.len();
+
+    let AdapterSet { adapters, scoring, min_block_size, max_block_size, .. } = adapter_set;
+    let (min_block_size, max_block_size) = (*min_block_size, *max_block_size);
+
+    let read_padded = PaddedBytes::from_bytes::<NucMatrix>(&seq, max_block_size);
+
+    let mut local = Block::<true, false, false, true, true>::new(min_block_size, len, max_block_size);
+
+    for adapter in adapters {
         let mut cigar = Cigar::new(min_block_size, len);
         
-        
-        let adapter_padded = PaddedBytes::from_str::<NucMatrix>(adapter, max_block_size);
         local.align(
-            &adapter_padded,
+            &adapter.padded,
             &read_padded,
-            &NW1,
-            gaps,
+            &scoring.matrix,
+            scoring.gaps,
             min_block_size..=max_block_size,
             0,
         );
 
         let res = local.res();
-        if res.score >= cmds.score_cutoff {
-            local.trace().cigar_eq(&adapter_padded, &read_padded, res.query_idx, res.reference_idx, &mut cigar);
+        if res.score >= score_cutoff {
+            local.trace().cigar_eq(&adapter.padded, &read_padded, res.query_idx, res.reference_idx, &mut cigar);
 
             let res_offset = AlignResult { score: res.score, query_idx: res.query_idx, reference_idx: res.reference_idx+offset };
+            let ref_start = res_offset.reference_idx - reference_len(&cigar);
             
             let result = Adapter::new(
-                fasta.get_name().split_once(' ').unwrap().0.to_owned(),
-                line.get_name().to_owned(),
+                fasta.get_id().to_owned(),
+                adapter.name.clone(),
                 cigar.to_string(),
-                res_offset
+                res_offset,
+                ref_start,
+                adapter.orientation
             );
             output.push(result);
         }
@@ -110,88 +473,375 @@ pub fn align_adapters(fasta: &mut Fasta, cmds: &AdapterArgs, output: &mut Vec<Ad
 }
 
 
-//Removes poly t at start or poly a at end and everything before that
-fn remove_t(fasta: &mut Fasta, smallest: i32) -> Result<Option<AlignResult>>{
-    let gaps = Gaps {
-        open: -2,
-        extend: -1,
-    };
-    let seq = fasta.get_sequence().to_owned();
-    let seq_first = &seq[0..min(300, seq.len())];
+//Number of read bases covered by an alignment, since insertions only consume the adapter
+fn reference_len(cigar: &Cigar) -> usize {
+    (0..cigar.len())
+        .map(|i| cigar.get(i))
+        .filter(|op_len| op_len.op != Operation::I)
+        .map(|op_len| op_len.len)
+        .sum()
+}
+
+//Removes poly t at start and everything before that, or if there is none poly a at end and everything after.
+//Returns the range of the original read that was kept
+fn remove_t(fasta: &mut Fasta, scoring: &AlignScoring, cmds: &AdapterConfig) -> Result<Option<Range<usize>>>{
+    let smallest = cmds.tail_score_cutoff.unwrap_or(cmds.score_cutoff);
+    let seq = fasta.get_sequence().to_contiguous().into_owned();
+    let len = seq.len();
+    let seq_first = &seq[0..min(cmds.tail_window, len)];
 
     let poly_t = 
-    "TTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTT";
+    b"TTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTTT";
 
 
-    let min_block_size = 256;
-    let max_block_size = 512;
-    
-    let read_padded_first = PaddedBytes::from_str::<NucMatrix>(seq_first, max_block_size);
-    let polyt_padded = PaddedBytes::from_str::<NucMatrix>(poly_t, max_block_size);
-    
-    
-    let mut local = Block::<false, false, true, false, true>::new(poly_t.len(), 500, max_block_size);
+    let (min_block_size, max_block_size) = scoring.poly_t_block_sizes(poly_t.len());
     
-    local.align(
-        &polyt_padded, 
-        &read_padded_first, 
-        &NW1, 
-        gaps, 
-        min_block_size..=max_block_size, 
-        0
-    );
+    let read_padded_first = PaddedBytes::from_bytes::<NucMatrix>(seq_first, max_block_size);
+    let polyt_padded = PaddedBytes::from_bytes::<NucMatrix>(poly_t, max_block_size);
 
+    let res_first = align_poly_t(&polyt_padded, &read_padded_first, scoring, min_block_size, max_block_size);
 
+    if res_first.score >= smallest {
+        let kept = res_first.reference_idx..len;
+        fasta.trim(kept.clone());
+        return Ok(Some(kept))
+    }
 
-    let res_first = local.res();
+    //Poly a at the end is poly t at the start of the reverse complement of the last bases,
+    //so the alignment end counts the bases to remove from the end of the read
+    let seq_last = reverse_complement(&seq[len.saturating_sub(cmds.tail_window)..])?;
+    let read_padded_last = PaddedBytes::from_bytes::<NucMatrix>(&seq_last, max_block_size);
 
-    if res_first.score >= smallest {
-        fasta.set_sequence(seq[res_first.reference_idx..].to_owned());
-        return Ok(Some(res_first))
-    
-    } else {
+    let res_last = align_poly_t(&polyt_padded, &read_padded_last, scoring, min_block_size, max_block_size);
 
-        let seq_new = fasta.get_sequence().to_owned();
-        let seq_reversed = reverse_complement(&seq_new)?;
-        let seq_rev_first = &seq_reversed[..min(seq_new.len(),300)];
+    if res_last.score >= smallest {
+        let kept = 0..len - res_last.reference_idx;
+        fasta.trim(kept.clone());
+        return Ok(Some(kept))
+    }
+    Ok(None)
+}
 
-        let read_padded_last = PaddedBytes::from_str::<NucMatrix>(seq_rev_first, max_block_size);
+//Aligns poly-T against the start of a read, as a local alignment ended by the X-drop if one is set
+fn align_poly_t(poly_t: &PaddedBytes, read: &PaddedBytes, scoring: &AlignScoring, min_block_size: usize, max_block_size: usize) -> AlignResult {
+    let sizes = min_block_size..=max_block_size;
+    match scoring.x_drop {
+        Some(x_drop) => {
+            let mut local = Block::<false, true, true, false, false>::new(poly_t.len(), 500, max_block_size);
+            local.align(poly_t, read, &scoring.matrix, scoring.gaps, sizes, x_drop);
+            local.res()
+        }
+        None => {
+            let mut local = Block::<false, false, true, false, true>::new(poly_t.len(), 500, max_block_size);
+            local.align(poly_t, read, &scoring.matrix, scoring.gaps, sizes, 0);
+            local.res()
+        }
+    }
+}
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::write_read_summary;
+
+    const ADAPTER: &[u8] = b"AATGTACTTCGTTCAGTTACGTATTGCT";
+
+    //Bases from a fixed LCG, so the insert doesn't look like the adapter
+    fn insert(len: usize) -> Vec<u8> {
+        let mut state: u32 = 12345;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                b"ACGT"[(state >> 16) as usize % 4]
+            })
+            .collect()
+    }
 
-        local.align(
-            &polyt_padded, 
-            &read_padded_last, 
-            &NW1, 
-            gaps, 
-            min_block_size..=max_block_size, 
-            0
-        );
+    fn adapter_config() -> AdapterConfig {
+        AdapterConfig {
+            adapters_file_path: String::new(),
+            longest_adapter: None,
+            score_cutoff: 20,
+            remove_t: false,
+            tail_score_cutoff: None,
+            tail_window: 300,
+            scoring: ScoringConfig::default(),
+        }
+    }
 
-        let res_last = local.res();
+    fn trim_config() -> TrimConfig {
+        TrimConfig {
+            adapters: adapter_config(),
+            end_size: 150,
+            min_length: 0,
+        }
+    }
 
-        if res_last.score >= smallest {
-            fasta.set_sequence(seq_rev_first[res_last.reference_idx..].to_owned());
-            return Ok(Some(res_last))
+    #[test]
+    fn adapter_set_encodes_each_orientation_once() {
+        let scoring = AlignScoring::new(&ScoringConfig::default()).unwrap();
+        //ACGCGTACGCGT is its own reverse complement, so it is only searched once
+        let sequences = vec![("first".to_owned(), ADAPTER.to_vec()), ("palindrome".to_owned(), b"ACGCGTACGCGT".to_vec())];
+        let adapter_set = AdapterSet::new(sequences, Some(40), scoring).unwrap();
+
+        assert_eq!(adapter_set.names(), ["first", "palindrome"]);
+        let orientations: Vec<_> = adapter_set.adapters.iter().map(|adapter| (adapter.index, adapter.orientation)).collect();
+        assert_eq!(orientations, [(0, Strand::Forward), (0, Strand::Reverse), (1, Strand::Forward)]);
+        assert!(adapter_set.min_block_size > 40);
+
+        //Either orientation of the first adapter scores the same against the read
+        let scores = adapter_set.scores(&reverse_complement(ADAPTER).unwrap());
+        assert_eq!(scores[0], adapter_set.scores(ADAPTER)[0]);
+        assert!(scores[0] > scores[1]);
+    }
+
+    #[test]
+    fn adapter_set_rejects_bad_lengths() {
+        let scoring = || AlignScoring::new(&ScoringConfig::default()).unwrap();
+        assert!(AdapterSet::new(Vec::new(), None, scoring()).is_err());
+        assert!(AdapterSet::new(vec![("first".to_owned(), ADAPTER.to_vec())], Some(10), scoring()).is_err());
+    }
+
+    #[test]
+    fn scoring_follows_preset_and_overrides() {
+        let perfect_score = |scoring: ScoringConfig| {
+            let scoring = AlignScoring::new(&scoring).unwrap();
+            let adapter_set = AdapterSet::new(vec![("adapter".to_owned(), ADAPTER.to_vec())], None, scoring).unwrap();
+            adapter_set.scores(&[insert(50), ADAPTER.to_vec(), insert(50)].concat())[0]
+        };
+        let len = ADAPTER.len() as i32;
+        assert_eq!(perfect_score(ScoringConfig::default()), len);
+        assert_eq!(perfect_score(ScoringConfig { preset: Some(Preset::R94), ..Default::default() }), 2 * len);
+        assert_eq!(perfect_score(ScoringConfig { preset: Some(Preset::R94), match_score: Some(3), ..Default::default() }), 3 * len);
+    }
+
+    #[test]
+    fn rejects_invalid_scoring() {
+        let invalid = [
+            ScoringConfig { match_score: Some(0), ..Default::default() },
+            ScoringConfig { mismatch_score: Some(1), ..Default::default() },
+            ScoringConfig { gap_open: Some(-1), gap_extend: Some(-1), ..Default::default() },
+            ScoringConfig { gap_extend: Some(0), ..Default::default() },
+            ScoringConfig { x_drop: Some(-1), ..Default::default() },
+        ];
+        for scoring in &invalid {
+            assert!(AlignScoring::new(scoring).is_err(), "{scoring:?}");
         }
+
+        let adapters = || vec![("adapter".to_owned(), ADAPTER.to_vec())];
+        let blocks = |min_block_size, max_block_size| {
+            let scoring = ScoringConfig { min_block_size, max_block_size, ..Default::default() };
+            AdapterSet::new(adapters(), None, AlignScoring::new(&scoring).unwrap())
+        };
+        assert!(blocks(Some(48), None).is_err());
+        assert!(blocks(Some(64), Some(32)).is_err());
+        //The block has to be longer than the adapter
+        assert!(blocks(Some(16), None).is_err());
+        assert!(blocks(Some(32), Some(64)).is_ok());
     }
-    Ok(None)
-}
 
-fn reverse_complement(seq: &str) -> Result<String> {
-    seq.bytes()
-        .rev()
-        .map(|b| match b {
-            b'A' => Ok('T'), 
-            b'T' => Ok('A'), 
-            b'C' => Ok('G'), 
-            b'G' => Ok('C'),
-            b'a' => Ok('t'), 
-            b't' => Ok('a'), 
-            b'c' => Ok('g'), 
-            b'g' => Ok('c'),
-            _ => bail!("Not a base pair - check format"),
-        })
-        .collect()
-}
+    #[test]
+    fn trims_same_adapter_at_both_ends() {
+        let cmds = trim_config();
+        let scoring = AlignScoring::new(&cmds.adapters.scoring).unwrap();
+        let adapter_set = AdapterSet::new(vec![("adapter".to_owned(), ADAPTER.to_vec())], None, scoring).unwrap();
+
+        let middle = insert(700);
+        let seq = [ADAPTER, &middle, ADAPTER].concat();
+        let mut fasta = Fasta::new("read".to_owned(), seq.into(), None);
+        let mut hits = Vec::new();
+        trim_adapters(&mut fasta, &adapter_set, &cmds, &mut hits).unwrap();
+
+        assert_eq!(fasta.get_sequence().len(), 700);
+        assert_eq!(fasta.get_sequence().to_contiguous().as_ref(), middle.as_slice());
+    }
+
+    #[test]
+    fn leaves_internal_adapters_and_trims_qualities() {
+        let cmds = trim_config();
+        let scoring = AlignScoring::new(&cmds.adapters.scoring).unwrap();
+        let adapter_set = AdapterSet::new(vec![("adapter".to_owned(), ADAPTER.to_vec())], None, scoring).unwrap();
+
+        //The second adapter is more than end_size from either end, so only the first is trimmed
+        let kept = [insert(200), ADAPTER.to_vec(), insert(200)].concat();
+        let seq = [&insert(20), ADAPTER, &kept].concat();
+        let quality: Vec<u8> = (0..seq.len()).map(|i| b'!' + (i % 40) as u8).collect();
+        let mut fasta = Fasta::new("read".to_owned(), seq.into(), Some(quality.clone()));
+        let mut hits = Vec::new();
+        trim_adapters(&mut fasta, &adapter_set, &cmds, &mut hits).unwrap();
+
+        assert_eq!(hits.len(), 2);
+        let start = 20 + ADAPTER.len();
+        assert_eq!(fasta.get_sequence().to_contiguous().as_ref(), kept.as_slice());
+        assert_eq!(fasta.get_quality(), Some(&quality[start..]));
+    }
+
+    #[test]
+    fn splits_reads_at_internal_adapters() {
+        let mut cmds = trim_config();
+        cmds.min_length = 100;
+        let scoring = AlignScoring::new(&cmds.adapters.scoring).unwrap();
+        let adapter_set = AdapterSet::new(vec![("adapter".to_owned(), ADAPTER.to_vec())], None, scoring).unwrap();
+
+        let bases = insert(650);
+        let (first, short, last) = (&bases[..300], &bases[300..350], &bases[350..]);
+        let seq = [ADAPTER, first, ADAPTER, short, &reverse_complement(ADAPTER).unwrap(), last].concat();
+        let fasta = Fasta::new("read desc".to_owned(), seq.into(), None);
+        let mut hits = Vec::new();
+        let mut output = Vec::new();
+        split_adapters(fasta, &adapter_set, &cmds, &mut hits, &mut output).unwrap();
+
+        //The start adapter is trimmed, the internal ones are cut out and the fragment between them is too short
+        assert_eq!(hits.len(), 3);
+        assert_eq!(output.len(), 2);
+        assert_eq!((output[0].get_id(), output[0].get_description()), ("read_1", Some("desc")));
+        assert_eq!(output[0].get_sequence().to_contiguous().as_ref(), first);
+        assert_eq!(output[1].get_id(), "read_2");
+        assert_eq!(output[1].get_sequence().to_contiguous().as_ref(), last);
+    }
 
+    #[test]
+    fn keeps_reads_without_internal_adapters_whole() {
+        let cmds = trim_config();
+        let scoring = AlignScoring::new(&cmds.adapters.scoring).unwrap();
+        let adapter_set = AdapterSet::new(vec![("adapter".to_owned(), ADAPTER.to_vec())], None, scoring).unwrap();
 
+        let fasta = Fasta::new("read".to_owned(), [insert(400), ADAPTER.to_vec()].concat().into(), None);
+        let mut output = Vec::new();
+        split_adapters(fasta, &adapter_set, &cmds, &mut Vec::new(), &mut output).unwrap();
+
+        assert_eq!(output.len(), 1);
+        assert_eq!(output[0].get_id(), "read");
+        assert_eq!(output[0].get_sequence().to_contiguous().as_ref(), insert(400).as_slice());
+    }
+
+    #[test]
+    fn classifies_reads_by_adapter_position() {
+        let mut cmds = ClassifyConfig { adapters: adapter_config(), end_size: 150 };
+        let second = b"CTTGCGGGCGGCGGACTCTCCTCTGAAG";
+        let sequences = vec![("first".to_owned(), ADAPTER.to_vec()), ("second".to_owned(), second.to_vec())];
+        let adapter_set = AdapterSet::new(sequences, None, AlignScoring::new(&cmds.adapters.scoring).unwrap()).unwrap();
+
+        let classify = |seq: Vec<u8>, cmds: &ClassifyConfig| {
+            let mut fasta = Fasta::new("read".to_owned(), seq.into(), None);
+            let summary = classify_read(&mut fasta, &adapter_set, cmds, &mut Vec::new()).unwrap();
+            let mut line = Vec::new();
+            write_read_summary(&mut line, &summary).unwrap();
+            (summary.class, String::from_utf8(line).unwrap())
+        };
+
+        let middle = insert(500);
+        assert_eq!(classify(middle.clone(), &cmds), (ReadClass::Clean, "read\t500\tclean\t.\n".to_owned()));
+        assert_eq!(classify([ADAPTER, &middle].concat(), &cmds).0, ReadClass::FivePrime);
+        assert_eq!(classify([&middle, ADAPTER].concat(), &cmds).0, ReadClass::ThreePrime);
+        assert_eq!(
+            classify([ADAPTER, &middle, second].concat(), &cmds),
+            (ReadClass::BothEnds, "read\t556\tboth-ends\tfirst,second\n".to_owned())
+        );
+        //An internal adapter outranks the ones at the ends
+        assert_eq!(classify([ADAPTER, &middle[..250], second, &middle[250..]].concat(), &cmds).0, ReadClass::Internal);
+
+        cmds.adapters.remove_t = true;
+        let (class, line) = classify([&[b'T'; 40], middle.as_slice()].concat(), &cmds);
+        assert_eq!(class, ReadClass::PolyTTrimmed);
+        //The length is from before the poly-T was removed
+        assert_eq!(line, "read\t540\tpoly-t-trimmed\t.\n");
+    }
+
+    #[test]
+    fn small_block_sizes_still_fit_poly_t() {
+        let mut cmds = adapter_config();
+        cmds.remove_t = true;
+        cmds.scoring.min_block_size = Some(32);
+        let scoring = AlignScoring::new(&cmds.scoring).unwrap();
+        let adapter_set = AdapterSet::new(vec![("adapter".to_owned(), ADAPTER.to_vec())], None, scoring).unwrap();
+
+        let middle = insert(500);
+        let seq = [b"ACGTAC".as_slice(), &[b'T'; 40], &middle].concat();
+        let mut fasta = Fasta::new("read".to_owned(), seq.into(), None);
+        let mut hits = Vec::new();
+        align_adapters(&mut fasta, &adapter_set, &cmds, &mut hits).unwrap();
+
+        assert_eq!(fasta.get_sequence().len(), 500);
+    }
+
+    #[test]
+    fn removes_poly_a_from_the_end() {
+        let mut cmds = adapter_config();
+        cmds.remove_t = true;
+        let scoring = AlignScoring::new(&cmds.scoring).unwrap();
+        let adapter_set = AdapterSet::new(vec![("adapter".to_owned(), ADAPTER.to_vec())], None, scoring).unwrap();
+
+        //The read ends in GCGC, so the poly-A alignment can't reach into it
+        let start = [insert(200), b"C".to_vec()].concat();
+        let kept = [start.as_slice(), ADAPTER, &start, b"GCGC"].concat();
+        let seq = [kept.as_slice(), &[b'A'; 40]].concat();
+        let mut fasta = Fasta::new("read".to_owned(), seq.into(), None);
+        let mut hits = Vec::new();
+        align_adapters(&mut fasta, &adapter_set, &cmds, &mut hits).unwrap();
+
+        //Only the tail is cut, leaving the read forward and the adapter where it was in the read
+        assert_eq!(fasta.get_sequence().to_contiguous().as_ref(), kept.as_slice());
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].get_orientation(), Strand::Forward);
+        assert_eq!((hits[0].get_ref_start(), hits[0].get_ref_end()), (201, 201 + ADAPTER.len()));
+    }
+
+    #[test]
+    fn removes_poly_t_from_the_start() {
+        let mut cmds = adapter_config();
+        cmds.remove_t = true;
+        let scoring = AlignScoring::new(&cmds.scoring).unwrap();
+        let adapter_set = AdapterSet::new(vec![("adapter".to_owned(), ADAPTER.to_vec())], None, scoring).unwrap();
+
+        let start = [b"C".to_vec(), insert(200)].concat();
+        let kept = [start.as_slice(), ADAPTER, &start].concat();
+        let seq = [[b'T'; 40].as_slice(), &kept].concat();
+        let mut fasta = Fasta::new("read".to_owned(), seq.into(), None);
+        let mut hits = Vec::new();
+        align_adapters(&mut fasta, &adapter_set, &cmds, &mut hits).unwrap();
+
+        //Positions count the removed poly-T, so they still match the untrimmed read
+        assert_eq!(fasta.get_sequence().to_contiguous().as_ref(), kept.as_slice());
+        assert_eq!(hits.len(), 1);
+        assert_eq!((hits[0].get_ref_start(), hits[0].get_ref_end()), (241, 241 + ADAPTER.len()));
+    }
+
+    #[test]
+    fn adapters_with_iupac_codes_are_found_reversed() {
+        let cmds = adapter_config();
+        let scoring = AlignScoring::new(&cmds.scoring).unwrap();
+        let mut adapter = ADAPTER.to_vec();
+        adapter[10] = b'Y';
+        let adapter_set = AdapterSet::new(vec![("adapter".to_owned(), adapter)], None, scoring).unwrap();
+
+        let seq = [insert(300), reverse_complement(ADAPTER).unwrap(), insert(300)].concat();
+        let fasta = Fasta::new("read".to_owned(), seq.into(), None);
+        let mut hits = Vec::new();
+        find_adapters(&fasta, &adapter_set, cmds.score_cutoff, 0, &mut hits).unwrap();
+
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].get_orientation(), Strand::Reverse);
+        assert!(hits[0].get_ref_start() <= 300 && hits[0].get_ref_end() >= 300 + ADAPTER.len() - 1);
+    }
+
+    #[test]
+    fn adapters_with_n_placeholders_are_found_once() {
+        let cmds = trim_config();
+        let scoring = AlignScoring::new(&cmds.adapters.scoring).unwrap();
+        let adapter = [[b'N'; 30].as_slice(), b"ACGT"].concat();
+        let adapter_set = AdapterSet::new(vec![("umi".to_owned(), adapter.clone())], None, scoring).unwrap();
+
+        let seq = [insert(300), adapter, insert(300)].concat();
+        let mut fasta = Fasta::new("read".to_owned(), seq.into(), None);
+        let mut hits = Vec::new();
+        trim_adapters(&mut fasta, &adapter_set, &cmds, &mut hits).unwrap();
+
+        //Masked hits can't be found again, so each orientation is reported at most once
+        let forward: Vec<_> = hits.iter().filter(|hit| hit.get_orientation() == Strand::Forward).collect();
+        assert_eq!(forward.len(), 1);
+        assert_eq!((forward[0].get_ref_start(), forward[0].get_ref_end()), (300, 334));
+        assert!(hits.len() <= 2);
+        assert!(hits.iter().all(|hit| hit.get_ref_start() < 334 && hit.get_ref_end() > 300));
+    }
+}
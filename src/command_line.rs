@@ -1,6 +1,11 @@
 use std::fmt::Display;
 
-use clap::{command, ArgGroup, Args, Parser, Subcommand};
+use clap::{ArgGroup, Args, Parser, Subcommand, ValueEnum};
+
+use palindromes::config::{
+    self, AdapterConfig, ClassifyConfig, Config, DemuxConfig, DirectConfig, ExtractConfig, FixedConfig, LongSpacerConfig,
+    MirrorConfig, ScoringConfig, TailConfig, TrimConfig, WfaConfig,
+};
 
 #[derive(Parser, Debug)]
 #[command(version, about)]
@@ -8,6 +13,34 @@ pub struct PalinArgs {
     ///Decide which algorithm should be used
     #[clap(subcommand)]
     pub mode: AlgorithmType,
+
+    ///Masks bases with a Phred quality below this as N before searching for palindromes. Needs FASTQ input
+    #[arg(long, global = true)]
+    pub min_quality: Option<u8>,
+
+    ///Checks the structure of the FASTQ input and reports every problem found, without running the mode
+    #[arg(long, global = true)]
+    pub validate: bool,
+
+    ///Where the sequence ID ends in each header, with the rest of the header as the description
+    #[arg(long, value_enum, global = true, default_value_t = HeaderSplit::Whitespace)]
+    pub header_split: HeaderSplit,
+
+    ///Adds a Description column with the rest of the header to the palindrome output
+    #[arg(long, global = true)]
+    pub keep_description: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum HeaderSplit {
+    ///The ID ends at the first space
+    Space,
+    ///The ID ends at the first tab
+    Tab,
+    ///The ID ends at the first space or tab
+    Whitespace,
+    ///The whole header is the ID
+    Full,
 }
 
 #[derive(Debug, Subcommand)]
@@ -16,8 +49,227 @@ pub enum AlgorithmType {
     Wfa(WfaArgs),
     ///Use fixed-mismatches algorithm, only allows fixed number mismatches and no indels
     ExactMatch(FixedArgs), 
+    ///Use WFA algorithm to find mirror repeats (H-DNA), where the second arm is the reverse of the first on the same strand
+    Mirror(MirrorArgs),
+    ///Find direct repeats, where the second copy has the same orientation as the first
+    Direct(DirectArgs),
+    ///Find palindromes with gaps of up to tens of kilobases, by pairing k-mer seeds and extending them with WFA
+    LongSpacer(LongSpacerArgs),
     ///Script for aligning adapter sequences, uses block-align library
-    Adapters(AdapterArgs)
+    Adapters(AdapterArgs),
+    ///Classifies every read by where adapters are found in it, with a summary of the counts
+    Classify(ClassifyArgs),
+    ///Finds homopolymer tails at the ends of reads and reports their positions and lengths
+    Tails(TailArgs),
+    ///Writes the reads with adapters at either end trimmed, keeping FASTQ qualities
+    Trim(TrimArgs),
+    ///Splits chimeric reads at adapters found away from their ends, after trimming the ends like trim
+    Split(TrimArgs),
+    ///Sorts reads into a file for each barcode, classifying them by the best barcode found at each end
+    Demux(DemuxArgs),
+    ///Writes palindromes as FASTA records instead of a TSV, using either search algorithm
+    Extract(ExtractArgs),
+    ///Writes a copy of the input with palindromes soft-masked (lowercase) or hard-masked (N)
+    Mask(MaskArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct ClassifyArgs {
+    #[command(flatten)]
+    pub adapters: AdapterArgs,
+
+    ///Adapters found within this many bases of either end count as end adapters, and any others as internal
+    #[arg(short, long, default_value_t = 150)]
+    pub end_size: usize,
+
+    ///File path for the table of reads in each class and reads with each adapter
+    #[arg(short, long)]
+    pub summary: String,
+}
+
+#[derive(Debug, Args)]
+pub struct TrimArgs {
+    #[command(flatten)]
+    pub adapters: AdapterArgs,
+
+    ///Adapters found within this many bases of either end are trimmed, along with everything past them
+    #[arg(short, long, default_value_t = 150)]
+    pub end_size: usize,
+
+    ///Reads or fragments shorter than this after trimming are discarded
+    #[arg(short, long, default_value_t = 1)]
+    pub min_length: usize,
+
+    ///Compresses the output with gzip
+    #[arg(long)]
+    pub gzip: bool,
+}
+
+#[derive(Debug, Args)]
+#[command(group = ArgGroup::new("file_type")
+    .required(true)
+    .args(&["fa", "fgz", "fq", "fqgz"]))]
+pub struct DemuxArgs {
+    #[arg(short, long = "input", required = true)]
+    ///Input file path
+    pub input_file: String,
+
+    /// Indicates the input file should be read in FASTA format
+    #[arg(long)]
+    pub fa: bool,
+
+    /// Indicates the input file should be read in compressed FASTA gzip format
+    #[arg(long)]
+    pub fgz: bool,
+
+    ///Indicates the input file should be read in FASTQ format
+    #[arg(long)]
+    pub fq: bool,
+
+    ///Indicates the input file should be read in compressed FASTQ gzip format
+    #[arg(long)]
+    pub fqgz: bool,
+
+    #[arg(short, long = "output")]
+    ///Output directory, which gets a file for each barcode, one for unclassified reads and a summary table
+    pub output_dir: String,
+
+    ///The file path for the list of barcode sequences. Must be fasta format
+    #[arg(short, long)]
+    pub barcodes_file_path: String,
+
+    ///Number of bases at each end of the read searched for barcodes
+    #[arg(short, long, default_value_t = 150)]
+    pub end_size: usize,
+
+    ///The smallest alignment score for a barcode to be assigned
+    #[arg(short = 'c', long)]
+    pub score_cutoff: i32,
+
+    ///How much higher the best barcode has to score than the next best at the same end
+    #[arg(short, long, default_value_t = 3)]
+    pub min_margin: i32,
+
+    ///Only assigns reads with the same barcode found at both ends
+    #[arg(long)]
+    pub both_ends: bool,
+
+    #[command(flatten)]
+    pub scoring: ScoringArgs,
+
+    ///Compresses the output with gzip
+    #[arg(long)]
+    pub gzip: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct MaskArgs {
+    ///Replaces palindromes with N instead of lowercasing them
+    #[arg(long)]
+    pub hard: bool,
+
+    ///Line width of the output FASTA, defaults to the line width of the input. 0 writes each sequence on one line
+    #[arg(short = 'w', long)]
+    pub line_width: Option<usize>,
+
+    ///Compresses the output with gzip
+    #[arg(long)]
+    pub gzip: bool,
+
+    ///Algorithm used to find the palindromes
+    #[clap(subcommand)]
+    pub search: SearchType,
+}
+
+#[derive(Debug, Args)]
+pub struct DirectArgs {
+    #[command(flatten)]
+    pub wfa: WfaArgs,
+
+    ///Maximum length of each copy of the repeat
+    #[arg(long, default_value_t = 100)]
+    pub max_arm: usize,
+
+    ///Also find inverted repeats (palindromes) in the same run
+    #[arg(long)]
+    pub inverted: bool,
+
+    ///Also find mirror repeats in the same run
+    #[arg(long)]
+    pub mirror: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct LongSpacerArgs {
+    #[command(flatten)]
+    pub wfa: WfaArgs,
+
+    ///Length of the k-mer seeds, at most 32
+    #[arg(short, long, default_value_t = 12)]
+    pub kmer: usize,
+
+    ///Minimum gap between the seeds
+    #[arg(long, default_value_t = 0)]
+    pub min_spacer: usize,
+
+    ///Maximum gap between the seeds
+    #[arg(long, default_value_t = 50000)]
+    pub max_spacer: usize,
+
+    ///Seeds with more matching k-mers than this within the gap range are skipped as repetitive
+    #[arg(long, default_value_t = 100)]
+    pub max_seed_hits: usize,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum SearchType {
+    ///Search with the WFA algorithm
+    Wfa(WfaArgs),
+    ///Search with the fixed-mismatches algorithm
+    ExactMatch(FixedArgs),
+    ///Search for mirror repeats with the WFA algorithm
+    Mirror(MirrorArgs),
+    ///Search for direct repeats
+    Direct(DirectArgs),
+    ///Search for palindromes with long gaps using k-mer seeds
+    LongSpacer(LongSpacerArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct MirrorArgs {
+    #[command(flatten)]
+    pub wfa: WfaArgs,
+
+    ///Minimum proportion of purines or pyrimidines in a mirror repeat, must be between 0 and 1
+    #[arg(long)]
+    pub purine_bias: Option<f32>,
+}
+
+#[derive(Debug, Args)]
+pub struct ExtractArgs {
+    ///Number of bases around the palindrome to include in the full record
+    #[arg(short, long, default_value_t = 0)]
+    pub flank: usize,
+
+    ///Also write the left arm as a separate record
+    #[arg(long)]
+    pub left_arm: bool,
+
+    ///Also write the reverse complement of the right arm as a separate record
+    #[arg(long)]
+    pub right_arm: bool,
+
+    ///Also write the gap between the arms as a separate record, if not empty
+    #[arg(long)]
+    pub spacer: bool,
+
+    ///Skips writing the full palindrome record
+    #[arg(long)]
+    pub no_full: bool,
+
+    ///Algorithm used to find the palindromes
+    #[clap(subcommand)]
+    pub search: SearchType,
 }
 
 #[derive(Debug, Args)]
@@ -53,19 +305,128 @@ pub struct AdapterArgs{
     #[arg(short, long)]
     pub adapters_file_path: String,
     
-    ///The length of the longest adapter in the file
+    ///The length of the longest adapter in the file, defaults to the length of the longest adapter found
     #[arg(short, long)]
-    pub longest_adapter: usize,
+    pub longest_adapter: Option<usize>,
 
     ///The smallest alignment score for an adapter sequence to be outputted
     #[arg(short = 'c', long)]
     pub score_cutoff: i32,
 
     ///Enables removing  poly-t sequences at the start and all the sequences before.
-    ///This also removes any poly-a sequences at the end if no poly-t are found
+    ///This also removes any poly-a sequences at the end and all the sequences after if no poly-t are found
+    #[arg(long)]
+    pub remove_t: bool,
+
+    ///The smallest alignment score for poly-t or poly-a to be removed, defaults to the score cutoff
+    #[arg(long)]
+    pub tail_score_cutoff: Option<i32>,
+
+    ///Number of bases at each end of the read searched for poly-t and poly-a
+    #[arg(long, default_value_t = 300)]
+    pub tail_window: usize,
+
+    #[command(flatten)]
+    pub scoring: ScoringArgs,
+}
+
+#[derive(Debug, Args)]
+#[command(group = ArgGroup::new("file_type")
+    .required(true)
+    .args(&["fa", "fgz", "fq", "fqgz"]))]
+#[command(group = ArgGroup::new("tail_type")
+    .required(true)
+    .multiple(true)
+    .args(&["poly_a", "poly_t", "poly_g"]))]
+pub struct TailArgs {
+    #[arg(short, long = "input", required = true)]
+    ///Input file path
+    pub input_file: String,
+
+    /// Indicates the input file should be read in FASTA format
+    #[arg(long)]
+    pub fa: bool,
+
+    /// Indicates the input file should be read in compressed FASTA gzip format
+    #[arg(long)]
+    pub fgz: bool,
+
+    ///Indicates the input file should be read in FASTQ format
+    #[arg(long)]
+    pub fq: bool,
+
+    ///Indicates the input file should be read in compressed FASTQ gzip format
+    #[arg(long)]
+    pub fqgz: bool,
+
+    #[arg(short, long = "output")]
+    ///Output file path.
+    pub output_file: String,
+
+    ///Searches for poly-A at the 3' end, reporting tails at least this long
+    #[arg(long)]
+    pub poly_a: Option<usize>,
+
+    ///Searches for poly-T at the 5' end, reporting tails at least this long
+    #[arg(long)]
+    pub poly_t: Option<usize>,
+
+    ///Searches for poly-G at the 3' end, reporting tails at least this long
+    #[arg(long)]
+    pub poly_g: Option<usize>,
+
+    ///Number of bases at the end of the read searched for each tail, which has to start within 3 bases of the end
+    #[arg(short, long, default_value_t = 300)]
+    pub window: usize,
+}
+
+//Scores for aligning adapters and poly-T against reads, where any option given overrides the preset
+#[derive(Debug, Args)]
+pub struct ScoringArgs {
+    ///Scores tuned for a sequencing chemistry. Without one, matches score 1, mismatches -1 and gaps -2/-1
+    #[arg(long, value_enum)]
+    pub preset: Option<Preset>,
+
+    ///Score for a matching base
+    #[arg(long)]
+    pub match_score: Option<i8>,
+
+    ///Score for a mismatched base, must be negative
+    #[arg(long, allow_negative_numbers = true)]
+    pub mismatch_score: Option<i8>,
+
+    ///Score for opening a gap, must be negative and lower than the extend score
+    #[arg(long, allow_negative_numbers = true)]
+    pub gap_open: Option<i8>,
+
+    ///Score for extending a gap, must be negative
+    #[arg(long, allow_negative_numbers = true)]
+    pub gap_extend: Option<i8>,
+
+    ///Smallest block size of the adapter aligner, defaults to the next power of two above the longest adapter. The poly-T alignment raises it to fit the poly-T
     #[arg(long)]
-    pub remove_t: bool
+    pub min_block_size: Option<usize>,
 
+    ///Largest block size of the adapter aligner, defaults to four times the smallest
+    #[arg(long)]
+    pub max_block_size: Option<usize>,
+
+    ///Stops the poly-T alignment once the score drops this far below the best, making it local. Adapter and barcode alignments always cover the whole read
+    #[arg(long)]
+    pub x_drop: Option<i32>,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Preset {
+    ///Nanopore R9.4.1, where indels are common
+    #[value(name = "r9.4")]
+    R94,
+    ///Nanopore R10.4.1
+    #[value(name = "r10.4")]
+    R104,
+    ///PacBio HiFi, where errors of any kind are rare
+    #[value(name = "hifi")]
+    Hifi,
 }
 
 #[derive(Debug, Args)]
@@ -161,6 +522,10 @@ pub struct WfaArgs {
     ///Max percentage of mismatches allowed in a palindrome, must be between 0 and 1
     #[arg(short = 'm', long, default_value_t = 0.05)]
     pub mismatch_proportion: f32,
+
+    ///Also scans the reverse complement, merging overlapping palindromes from both strands
+    #[arg(long)]
+    pub both_strands: bool,
 }
 
 impl Display for PalinArgs{
@@ -169,37 +534,290 @@ impl Display for PalinArgs{
             f,
             "{}",
             self.mode
-        )
+        )?;
+        if let Some(min_quality) = self.min_quality {
+            write!(f, "\nMin quality: {}", min_quality)?;
+        }
+        Ok(())
     }
 }
 
 impl Display for AlgorithmType{
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self{
-            AlgorithmType::Wfa(cmds) => 
+            AlgorithmType::Wfa(cmds) => write!(f, "{}", cmds),
+            AlgorithmType::ExactMatch(cmds) => write!(f, "{}", cmds),
+            AlgorithmType::Mirror(cmds) => write!(f, "{}", cmds),
+            AlgorithmType::Direct(cmds) => write!(f, "{}", cmds),
+            AlgorithmType::LongSpacer(cmds) => write!(f, "{}", cmds),
+            AlgorithmType::Adapters(_cmds) => Ok(()),
+            AlgorithmType::Classify(cmds) => write!(f, "End size: {}", cmds.end_size),
+            AlgorithmType::Tails(cmds) => {
+                write!(f, "Window: {}", cmds.window)?;
+                for (tail, min_length) in [("Poly-A", cmds.poly_a), ("Poly-T", cmds.poly_t), ("Poly-G", cmds.poly_g)] {
+                    if let Some(min_length) = min_length {
+                        write!(f, "\n{} min length: {}", tail, min_length)?;
+                    }
+                }
+                Ok(())
+            }
+            AlgorithmType::Demux(cmds) =>
                 write!(
                     f,
-                    "Min length: {}\nMax gap length: {}\nMatch bonus: {}\nMismatch penalty: {}\nX-drop: {}\nMax mismatch proportion: {}",
-                    cmds.min_length, cmds.gap_len, cmds.match_bonus, cmds.mismatch_penalty, cmds.x_drop, cmds.mismatch_proportion
+                    "End size: {}\nScore cutoff: {}\nMin margin: {}\nBoth ends: {}",
+                    cmds.end_size, cmds.score_cutoff, cmds.min_margin, cmds.both_ends
             ),
-            AlgorithmType::ExactMatch(cmds) => 
+            AlgorithmType::Trim(cmds) | AlgorithmType::Split(cmds) =>
                 write!(
                     f,
-                    "Min length: {}\nMax gap length: {}\nMismatches allowed: {}",
-                    cmds.len, cmds.gap_len, cmds.mismatches
+                    "End size: {}\nMin length: {}",
+                    cmds.end_size, cmds.min_length
+            ),
+            AlgorithmType::Extract(cmds) => 
+                write!(
+                    f,
+                    "{}\nFlank: {}",
+                    cmds.search, cmds.flank
+            ),
+            AlgorithmType::Mask(cmds) => 
+                write!(
+                    f,
+                    "{}\nHard mask: {}",
+                    cmds.search, cmds.hard
             ),
-            AlgorithmType::Adapters(_cmds) => Ok(())
         }
         
     }
 }
 
+impl Display for SearchType{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self{
+            SearchType::Wfa(cmds) => write!(f, "{}", cmds),
+            SearchType::ExactMatch(cmds) => write!(f, "{}", cmds),
+            SearchType::Mirror(cmds) => write!(f, "{}", cmds),
+            SearchType::Direct(cmds) => write!(f, "{}", cmds),
+            SearchType::LongSpacer(cmds) => write!(f, "{}", cmds),
+        }
+    }
+}
+
+impl Display for WfaArgs{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Min length: {}\nMax gap length: {}\nMatch bonus: {}\nMismatch penalty: {}\nX-drop: {}\nMax mismatch proportion: {}\nBoth strands: {}",
+            self.min_length, self.gap_len, self.match_bonus, self.mismatch_penalty, self.x_drop, self.mismatch_proportion, self.both_strands
+        )
+    }
+}
+
+impl Display for FixedArgs{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Min length: {}\nMax gap length: {}\nMismatches allowed: {}",
+            self.len, self.gap_len, self.mismatches
+        )
+    }
+}
+
+impl Display for DirectArgs{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}\nMax arm length: {}\nInverted repeats: {}\nMirror repeats: {}",
+            self.wfa, self.max_arm, self.inverted, self.mirror
+        )
+    }
+}
+
+impl Display for LongSpacerArgs{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}\nK-mer length: {}\nGap range: {}-{}\nMax seed hits: {}",
+            self.wfa, self.kmer, self.min_spacer, self.max_spacer, self.max_seed_hits
+        )
+    }
+}
+
+impl Display for MirrorArgs{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.wfa)?;
+        if let Some(bias) = self.purine_bias {
+            write!(f, "\nMin purine/pyrimidine bias: {}", bias)?;
+        }
+        Ok(())
+    }
+}
+
+//Conversions to the library settings, which leave out the input and output options
+impl From<&WfaArgs> for WfaConfig {
+    fn from(cmds: &WfaArgs) -> Self {
+        WfaConfig {
+            min_length: cmds.min_length,
+            gap_len: cmds.gap_len,
+            match_bonus: cmds.match_bonus,
+            mismatch_penalty: cmds.mismatch_penalty,
+            x_drop: cmds.x_drop,
+            mismatch_proportion: cmds.mismatch_proportion,
+            both_strands: cmds.both_strands,
+        }
+    }
+}
+
+impl From<&FixedArgs> for FixedConfig {
+    fn from(cmds: &FixedArgs) -> Self {
+        FixedConfig { min_length: cmds.len, gap_len: cmds.gap_len, mismatches: cmds.mismatches }
+    }
+}
+
+impl From<&MirrorArgs> for MirrorConfig {
+    fn from(cmds: &MirrorArgs) -> Self {
+        MirrorConfig { wfa: (&cmds.wfa).into(), purine_bias: cmds.purine_bias }
+    }
+}
+
+impl From<&DirectArgs> for DirectConfig {
+    fn from(cmds: &DirectArgs) -> Self {
+        DirectConfig { wfa: (&cmds.wfa).into(), max_arm: cmds.max_arm, inverted: cmds.inverted, mirror: cmds.mirror }
+    }
+}
+
+impl From<&LongSpacerArgs> for LongSpacerConfig {
+    fn from(cmds: &LongSpacerArgs) -> Self {
+        LongSpacerConfig {
+            wfa: (&cmds.wfa).into(),
+            kmer: cmds.kmer,
+            min_spacer: cmds.min_spacer,
+            max_spacer: cmds.max_spacer,
+            max_seed_hits: cmds.max_seed_hits,
+        }
+    }
+}
+
+impl From<HeaderSplit> for config::HeaderSplit {
+    fn from(header_split: HeaderSplit) -> Self {
+        match header_split {
+            HeaderSplit::Space => config::HeaderSplit::Space,
+            HeaderSplit::Tab => config::HeaderSplit::Tab,
+            HeaderSplit::Whitespace => config::HeaderSplit::Whitespace,
+            HeaderSplit::Full => config::HeaderSplit::Full,
+        }
+    }
+}
+
+impl From<Preset> for config::Preset {
+    fn from(preset: Preset) -> Self {
+        match preset {
+            Preset::R94 => config::Preset::R94,
+            Preset::R104 => config::Preset::R104,
+            Preset::Hifi => config::Preset::Hifi,
+        }
+    }
+}
+
+impl From<&ScoringArgs> for ScoringConfig {
+    fn from(cmds: &ScoringArgs) -> Self {
+        ScoringConfig {
+            preset: cmds.preset.map(Into::into),
+            match_score: cmds.match_score,
+            mismatch_score: cmds.mismatch_score,
+            gap_open: cmds.gap_open,
+            gap_extend: cmds.gap_extend,
+            min_block_size: cmds.min_block_size,
+            max_block_size: cmds.max_block_size,
+            x_drop: cmds.x_drop,
+        }
+    }
+}
+
+impl From<&AdapterArgs> for AdapterConfig {
+    fn from(cmds: &AdapterArgs) -> Self {
+        AdapterConfig {
+            adapters_file_path: cmds.adapters_file_path.clone(),
+            longest_adapter: cmds.longest_adapter,
+            score_cutoff: cmds.score_cutoff,
+            remove_t: cmds.remove_t,
+            tail_score_cutoff: cmds.tail_score_cutoff,
+            tail_window: cmds.tail_window,
+            scoring: (&cmds.scoring).into(),
+        }
+    }
+}
+
+impl From<&TrimArgs> for TrimConfig {
+    fn from(cmds: &TrimArgs) -> Self {
+        TrimConfig { adapters: (&cmds.adapters).into(), end_size: cmds.end_size, min_length: cmds.min_length }
+    }
+}
+
+impl From<&ClassifyArgs> for ClassifyConfig {
+    fn from(cmds: &ClassifyArgs) -> Self {
+        ClassifyConfig { adapters: (&cmds.adapters).into(), end_size: cmds.end_size }
+    }
+}
+
+impl From<&DemuxArgs> for DemuxConfig {
+    fn from(cmds: &DemuxArgs) -> Self {
+        DemuxConfig {
+            barcodes_file_path: cmds.barcodes_file_path.clone(),
+            end_size: cmds.end_size,
+            score_cutoff: cmds.score_cutoff,
+            min_margin: cmds.min_margin,
+            both_ends: cmds.both_ends,
+            scoring: (&cmds.scoring).into(),
+        }
+    }
+}
+
+impl From<&TailArgs> for TailConfig {
+    fn from(cmds: &TailArgs) -> Self {
+        TailConfig { poly_a: cmds.poly_a, poly_t: cmds.poly_t, poly_g: cmds.poly_g, window: cmds.window }
+    }
+}
+
+impl From<&ExtractArgs> for ExtractConfig {
+    fn from(cmds: &ExtractArgs) -> Self {
+        ExtractConfig {
+            flank: cmds.flank,
+            left_arm: cmds.left_arm,
+            right_arm: cmds.right_arm,
+            spacer: cmds.spacer,
+            no_full: cmds.no_full,
+        }
+    }
+}
+
+impl From<&SearchType> for Config {
+    fn from(search: &SearchType) -> Self {
+        match search {
+            SearchType::Wfa(cmds) => Config::Wfa(cmds.into()),
+            SearchType::ExactMatch(cmds) => Config::ExactMatch(cmds.into()),
+            SearchType::Mirror(cmds) => Config::Mirror(cmds.into()),
+            SearchType::Direct(cmds) => Config::Direct(cmds.into()),
+            SearchType::LongSpacer(cmds) => Config::LongSpacer(cmds.into()),
+        }
+    }
+}
+
 impl AlgorithmType {
     pub fn input_file(&self) -> &str {
         match self {
             AlgorithmType::Wfa(cmds) => &cmds.input_file,
             AlgorithmType::ExactMatch(cmds) => &cmds.input_file,
+            AlgorithmType::Mirror(cmds) => &cmds.wfa.input_file,
+            AlgorithmType::Direct(cmds) => &cmds.wfa.input_file,
+            AlgorithmType::LongSpacer(cmds) => &cmds.wfa.input_file,
             AlgorithmType::Adapters(cmds) => &cmds.input_file,
+            AlgorithmType::Tails(cmds) => &cmds.input_file,
+            AlgorithmType::Classify(cmds) => &cmds.adapters.input_file,
+            AlgorithmType::Trim(cmds) => &cmds.adapters.input_file,
+            AlgorithmType::Split(cmds) => &cmds.adapters.input_file,
+            AlgorithmType::Demux(cmds) => &cmds.input_file,
+            AlgorithmType::Extract(cmds) => cmds.search.input_file(),
+            AlgorithmType::Mask(cmds) => cmds.search.input_file(),
         }
     }
 
@@ -207,7 +825,17 @@ impl AlgorithmType {
         match self {
             AlgorithmType::Wfa(cmds) => cmds.fa,
             AlgorithmType::ExactMatch(cmds) => cmds.fa,
+            AlgorithmType::Mirror(cmds) => cmds.wfa.fa,
+            AlgorithmType::Direct(cmds) => cmds.wfa.fa,
+            AlgorithmType::LongSpacer(cmds) => cmds.wfa.fa,
             AlgorithmType::Adapters(cmds) => cmds.fa,
+            AlgorithmType::Tails(cmds) => cmds.fa,
+            AlgorithmType::Classify(cmds) => cmds.adapters.fa,
+            AlgorithmType::Trim(cmds) => cmds.adapters.fa,
+            AlgorithmType::Split(cmds) => cmds.adapters.fa,
+            AlgorithmType::Demux(cmds) => cmds.fa,
+            AlgorithmType::Extract(cmds) => cmds.search.is_fa(),
+            AlgorithmType::Mask(cmds) => cmds.search.is_fa(),
         }
     }
 
@@ -215,7 +843,17 @@ impl AlgorithmType {
         match self {
             AlgorithmType::Wfa(cmds) => cmds.fgz,
             AlgorithmType::ExactMatch(cmds) => cmds.fgz,
+            AlgorithmType::Mirror(cmds) => cmds.wfa.fgz,
+            AlgorithmType::Direct(cmds) => cmds.wfa.fgz,
+            AlgorithmType::LongSpacer(cmds) => cmds.wfa.fgz,
             AlgorithmType::Adapters(cmds) => cmds.fgz,
+            AlgorithmType::Tails(cmds) => cmds.fgz,
+            AlgorithmType::Classify(cmds) => cmds.adapters.fgz,
+            AlgorithmType::Trim(cmds) => cmds.adapters.fgz,
+            AlgorithmType::Split(cmds) => cmds.adapters.fgz,
+            AlgorithmType::Demux(cmds) => cmds.fgz,
+            AlgorithmType::Extract(cmds) => cmds.search.is_fgz(),
+            AlgorithmType::Mask(cmds) => cmds.search.is_fgz(),
         }
     }
 
@@ -223,7 +861,17 @@ impl AlgorithmType {
         match self {
             AlgorithmType::Wfa(cmds) => cmds.fq,
             AlgorithmType::ExactMatch(cmds) => cmds.fq,
+            AlgorithmType::Mirror(cmds) => cmds.wfa.fq,
+            AlgorithmType::Direct(cmds) => cmds.wfa.fq,
+            AlgorithmType::LongSpacer(cmds) => cmds.wfa.fq,
             AlgorithmType::Adapters(cmds) => cmds.fq,
+            AlgorithmType::Tails(cmds) => cmds.fq,
+            AlgorithmType::Classify(cmds) => cmds.adapters.fq,
+            AlgorithmType::Trim(cmds) => cmds.adapters.fq,
+            AlgorithmType::Split(cmds) => cmds.adapters.fq,
+            AlgorithmType::Demux(cmds) => cmds.fq,
+            AlgorithmType::Extract(cmds) => cmds.search.is_fq(),
+            AlgorithmType::Mask(cmds) => cmds.search.is_fq(),
         }
     }
 
@@ -231,15 +879,95 @@ impl AlgorithmType {
         match self {
             AlgorithmType::Wfa(cmds) => cmds.fqgz,
             AlgorithmType::ExactMatch(cmds) => cmds.fqgz,
+            AlgorithmType::Mirror(cmds) => cmds.wfa.fqgz,
+            AlgorithmType::Direct(cmds) => cmds.wfa.fqgz,
+            AlgorithmType::LongSpacer(cmds) => cmds.wfa.fqgz,
             AlgorithmType::Adapters(cmds) => cmds.fqgz,
+            AlgorithmType::Tails(cmds) => cmds.fqgz,
+            AlgorithmType::Classify(cmds) => cmds.adapters.fqgz,
+            AlgorithmType::Trim(cmds) => cmds.adapters.fqgz,
+            AlgorithmType::Split(cmds) => cmds.adapters.fqgz,
+            AlgorithmType::Demux(cmds) => cmds.fqgz,
+            AlgorithmType::Extract(cmds) => cmds.search.is_fqgz(),
+            AlgorithmType::Mask(cmds) => cmds.search.is_fqgz(),
         }
     }
     pub fn output_file(&self) -> &str {
         match self {
             AlgorithmType::Wfa(cmds) => &cmds.output_file,
             AlgorithmType::ExactMatch(cmds) => &cmds.output_file,
+            AlgorithmType::Mirror(cmds) => &cmds.wfa.output_file,
+            AlgorithmType::Direct(cmds) => &cmds.wfa.output_file,
+            AlgorithmType::LongSpacer(cmds) => &cmds.wfa.output_file,
             AlgorithmType::Adapters(cmds) => &cmds.output_file,
+            AlgorithmType::Tails(cmds) => &cmds.output_file,
+            AlgorithmType::Classify(cmds) => &cmds.adapters.output_file,
+            AlgorithmType::Trim(cmds) => &cmds.adapters.output_file,
+            AlgorithmType::Split(cmds) => &cmds.adapters.output_file,
+            AlgorithmType::Demux(cmds) => &cmds.output_dir,
+            AlgorithmType::Extract(cmds) => cmds.search.output_file(),
+            AlgorithmType::Mask(cmds) => cmds.search.output_file(),
         }
     }
 }
 
+impl SearchType {
+    pub fn input_file(&self) -> &str {
+        match self {
+            SearchType::Wfa(cmds) => &cmds.input_file,
+            SearchType::ExactMatch(cmds) => &cmds.input_file,
+            SearchType::Mirror(cmds) => &cmds.wfa.input_file,
+            SearchType::Direct(cmds) => &cmds.wfa.input_file,
+            SearchType::LongSpacer(cmds) => &cmds.wfa.input_file,
+        }
+    }
+
+    pub fn is_fa(&self) -> bool {
+        match self {
+            SearchType::Wfa(cmds) => cmds.fa,
+            SearchType::ExactMatch(cmds) => cmds.fa,
+            SearchType::Mirror(cmds) => cmds.wfa.fa,
+            SearchType::Direct(cmds) => cmds.wfa.fa,
+            SearchType::LongSpacer(cmds) => cmds.wfa.fa,
+        }
+    }
+
+    pub fn is_fgz(&self) -> bool {
+        match self {
+            SearchType::Wfa(cmds) => cmds.fgz,
+            SearchType::ExactMatch(cmds) => cmds.fgz,
+            SearchType::Mirror(cmds) => cmds.wfa.fgz,
+            SearchType::Direct(cmds) => cmds.wfa.fgz,
+            SearchType::LongSpacer(cmds) => cmds.wfa.fgz,
+        }
+    }
+
+    pub fn is_fq(&self) -> bool {
+        match self {
+            SearchType::Wfa(cmds) => cmds.fq,
+            SearchType::ExactMatch(cmds) => cmds.fq,
+            SearchType::Mirror(cmds) => cmds.wfa.fq,
+            SearchType::Direct(cmds) => cmds.wfa.fq,
+            SearchType::LongSpacer(cmds) => cmds.wfa.fq,
+        }
+    }
+
+    pub fn is_fqgz(&self) -> bool {
+        match self {
+            SearchType::Wfa(cmds) => cmds.fqgz,
+            SearchType::ExactMatch(cmds) => cmds.fqgz,
+            SearchType::Mirror(cmds) => cmds.wfa.fqgz,
+            SearchType::Direct(cmds) => cmds.wfa.fqgz,
+            SearchType::LongSpacer(cmds) => cmds.wfa.fqgz,
+        }
+    }
+    pub fn output_file(&self) -> &str {
+        match self {
+            SearchType::Wfa(cmds) => &cmds.output_file,
+            SearchType::ExactMatch(cmds) => &cmds.output_file,
+            SearchType::Mirror(cmds) => &cmds.wfa.output_file,
+            SearchType::Direct(cmds) => &cmds.wfa.output_file,
+            SearchType::LongSpacer(cmds) => &cmds.wfa.output_file,
+        }
+    }
+}
@@ -0,0 +1,233 @@
+//Settings for each palindrome search, kept apart from the command line so the search can be used as a library.
+//The defaults are the same as the command line defaults
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WfaConfig {
+    //Minimum palindrome arm length
+    pub min_length: usize,
+    //Maximum gap length in a palindrome
+    pub gap_len: usize,
+    //Bonus for matches in scoring, must be positive
+    pub match_bonus: f32,
+    //Penalty for mismatches in scoring, must be positive since it is subtracted
+    pub mismatch_penalty: f32,
+    //Maximum score drop allowed before pruning
+    pub x_drop: f32,
+    //Max proportion of mismatches allowed in a palindrome, between 0 and 1
+    pub mismatch_proportion: f32,
+    //Also scans the reverse complement, merging overlapping palindromes from both strands
+    pub both_strands: bool,
+}
+
+impl Default for WfaConfig {
+    fn default() -> Self {
+        Self {
+            min_length: 10,
+            gap_len: 3,
+            match_bonus: 1.0,
+            mismatch_penalty: 4.0,
+            x_drop: 20.0,
+            mismatch_proportion: 0.05,
+            both_strands: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FixedConfig {
+    //Minimum palindrome arm length
+    pub min_length: usize,
+    //Maximum gap length in a palindrome
+    pub gap_len: usize,
+    //Max number of mismatches
+    pub mismatches: u32,
+}
+
+impl Default for FixedConfig {
+    fn default() -> Self {
+        Self { min_length: 10, gap_len: 3, mismatches: 4 }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MirrorConfig {
+    pub wfa: WfaConfig,
+    //Minimum proportion of purines or pyrimidines in a mirror repeat, between 0 and 1
+    pub purine_bias: Option<f32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DirectConfig {
+    pub wfa: WfaConfig,
+    //Maximum length of each copy of the repeat
+    pub max_arm: usize,
+    //Also find inverted repeats (palindromes) in the same run
+    pub inverted: bool,
+    //Also find mirror repeats in the same run
+    pub mirror: bool,
+}
+
+impl Default for DirectConfig {
+    fn default() -> Self {
+        Self { wfa: WfaConfig::default(), max_arm: 100, inverted: false, mirror: false }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LongSpacerConfig {
+    pub wfa: WfaConfig,
+    //Length of the k-mer seeds, at most 32
+    pub kmer: usize,
+    //Minimum gap between the seeds
+    pub min_spacer: usize,
+    //Maximum gap between the seeds
+    pub max_spacer: usize,
+    //Seeds with more matching k-mers than this within the gap range are skipped as repetitive
+    pub max_seed_hits: usize,
+}
+
+impl Default for LongSpacerConfig {
+    fn default() -> Self {
+        Self { wfa: WfaConfig::default(), kmer: 12, min_spacer: 0, max_spacer: 50000, max_seed_hits: 100 }
+    }
+}
+
+//Which search to run, with its settings
+#[derive(Debug, Clone, PartialEq)]
+pub enum Config {
+    Wfa(WfaConfig),
+    ExactMatch(FixedConfig),
+    Mirror(MirrorConfig),
+    Direct(DirectConfig),
+    LongSpacer(LongSpacerConfig),
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config::Wfa(WfaConfig::default())
+    }
+}
+
+//Where the ID ends in a FASTA or FASTQ header, with the rest of the header as the description
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HeaderSplit {
+    //The ID ends at the first space
+    Space,
+    //The ID ends at the first tab
+    Tab,
+    //The ID ends at the first space or tab
+    #[default]
+    Whitespace,
+    //The whole header is the ID
+    Full,
+}
+
+impl HeaderSplit {
+    //Length of the ID at the start of the header
+    pub fn id_len(&self, header: &str) -> usize {
+        let end = match self {
+            HeaderSplit::Space => header.find(' '),
+            HeaderSplit::Tab => header.find('\t'),
+            HeaderSplit::Whitespace => header.find([' ', '\t']),
+            HeaderSplit::Full => None,
+        };
+        end.unwrap_or(header.len())
+    }
+}
+
+//Aligner scores tuned for a sequencing chemistry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preset {
+    //Nanopore R9.4.1, where indels are common
+    R94,
+    //Nanopore R10.4.1
+    R104,
+    //PacBio HiFi, where errors of any kind are rare
+    Hifi,
+}
+
+//Scores for aligning adapters and poly-T against reads, where any value given overrides the preset
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScoringConfig {
+    pub preset: Option<Preset>,
+    pub match_score: Option<i8>,
+    pub mismatch_score: Option<i8>,
+    pub gap_open: Option<i8>,
+    pub gap_extend: Option<i8>,
+    //Block size range of the adapter alignment, fitted to the longest adapter when not given.
+    //The poly-T alignment raises these to fit the poly-T
+    pub min_block_size: Option<usize>,
+    pub max_block_size: Option<usize>,
+    //Stops the poly-T alignment once the score drops this far below the best, with no effect on adapter alignments
+    pub x_drop: Option<i32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdapterConfig {
+    //FASTA file of the adapter sequences
+    pub adapters_file_path: String,
+    //Length of the longest adapter, defaults to the longest adapter in the file
+    pub longest_adapter: Option<usize>,
+    //Smallest alignment score for an adapter to be reported
+    pub score_cutoff: i32,
+    //Removes poly-T at the start and everything before it, or poly-A at the end and everything after it
+    pub remove_t: bool,
+    //Smallest alignment score for a tail to be removed, defaults to score_cutoff
+    pub tail_score_cutoff: Option<i32>,
+    //Number of bases at each end of the read searched for tails
+    pub tail_window: usize,
+    pub scoring: ScoringConfig,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrimConfig {
+    pub adapters: AdapterConfig,
+    //Adapters within this many bases of either end are trimmed, along with everything past them
+    pub end_size: usize,
+    //Reads or fragments shorter than this after trimming are discarded
+    pub min_length: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClassifyConfig {
+    pub adapters: AdapterConfig,
+    //Adapters within this many bases of either end count as end adapters, and any others as internal
+    pub end_size: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DemuxConfig {
+    //FASTA file of the barcode sequences
+    pub barcodes_file_path: String,
+    //Number of bases at each end of the read searched for barcodes
+    pub end_size: usize,
+    //Smallest alignment score for a barcode to be assigned
+    pub score_cutoff: i32,
+    //How much higher the best barcode has to score than the next best at the same end
+    pub min_margin: i32,
+    //Only assigns reads with the same barcode found at both ends
+    pub both_ends: bool,
+    pub scoring: ScoringConfig,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TailConfig {
+    //Minimum length of each type of tail, where None skips that type
+    pub poly_a: Option<usize>,
+    pub poly_t: Option<usize>,
+    pub poly_g: Option<usize>,
+    //Number of bases at the end of the read searched for each tail
+    pub window: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExtractConfig {
+    //Number of bases around the palindrome included in the full record
+    pub flank: usize,
+    //Also writes the left arm, the reverse complement of the right arm and the spacer as separate records
+    pub left_arm: bool,
+    pub right_arm: bool,
+    pub spacer: bool,
+    //Skips writing the full palindrome record
+    pub no_full: bool,
+}
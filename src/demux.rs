@@ -0,0 +1,195 @@
+use std::{
+    cmp::min,
+    collections::HashMap,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{self, Path},
+};
+
+use anyhow::{ensure, Result};
+
+use crate::{
+    adapters::{AdapterSet, AlignScoring},
+    config::DemuxConfig,
+    fasta_parsing::{get_writer, open_reader, Fasta, FastaIterator, ReadWriter},
+    output::BUFF_SIZE,
+};
+
+const UNCLASSIFIED: &str = "unclassified";
+const SUMMARY: &str = "summary";
+
+//Writes every read to the file of its barcode in output_dir, along with a summary of the counts
+pub fn demux_reads(cmds: &DemuxConfig, output_dir: &str, gzip: bool, iterator: Box<dyn Iterator<Item = Result<Fasta>>>, fastq: bool) -> Result<()> {
+    let barcodes = load_barcodes(&cmds.barcodes_file_path, AlignScoring::new(&cmds.scoring)?)?;
+    let names: Vec<&str> = barcodes.names().iter().map(String::as_str).collect();
+
+    fs::create_dir_all(output_dir)?;
+    let extension = match (fastq, gzip) {
+        (true, false) => "fastq",
+        (true, true) => "fastq.gz",
+        (false, false) => "fasta",
+        (false, true) => "fasta.gz",
+    };
+
+    let mut writers = HashMap::new();
+    //Reads and bases for each barcode, with unclassified reads last
+    let mut counts = vec![(0, 0); names.len() + 1];
+
+    for fasta in iterator {
+        let fasta = fasta?;
+        let barcode = classify(&fasta, &barcodes, cmds);
+        let name = barcode.map_or(UNCLASSIFIED, |barcode| names[barcode]);
+
+        if !writers.contains_key(name) {
+            let path = Path::new(output_dir).join(format!("{}.{}", name, extension));
+            let writer = get_writer(&path.to_string_lossy(), gzip)?;
+            writers.insert(name, ReadWriter::new(writer, fastq));
+        }
+        writers.get_mut(name).unwrap().write(&fasta)?;
+
+        let count = &mut counts[barcode.unwrap_or(names.len())];
+        count.0 += 1;
+        count.1 += fasta.get_sequence().len();
+    }
+    for writer in writers.values_mut() {
+        writer.flush()?;
+    }
+
+    let summary = File::create(Path::new(output_dir).join(format!("{}.tsv", SUMMARY)))?;
+    let mut writer = BufWriter::with_capacity(BUFF_SIZE, summary);
+    writeln!(writer, "Barcode\tReads\tBases")?;
+    for (name, (reads, bases)) in names.iter().chain([&UNCLASSIFIED]).zip(counts) {
+        writeln!(writer, "{}\t{}\t{}", name, reads, bases)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+//Loads the barcodes named by their IDs, which are checked since they name the output files
+fn load_barcodes(file_name: &str, scoring: AlignScoring) -> Result<AdapterSet> {
+    let mut sequences: Vec<(String, Vec<u8>)> = Vec::new();
+    for fasta in FastaIterator::new(open_reader(file_name, false)?) {
+        let fasta = fasta?;
+        let id = fasta.get_id();
+        check_barcode_id(id, &sequences)?;
+        sequences.push((id.to_owned(), fasta.get_sequence().to_contiguous().into_owned()));
+    }
+    ensure!(!sequences.is_empty(), "No barcodes found in {}", file_name);
+    AdapterSet::new(sequences, None, scoring)
+}
+
+fn check_barcode_id(id: &str, loaded: &[(String, Vec<u8>)]) -> Result<()> {
+    ensure!(!id.is_empty(), "Barcode with an empty ID");
+    ensure!(!id.contains(path::is_separator), "Barcode ID {} can't contain a path separator", id);
+    ensure!(id != UNCLASSIFIED && id != SUMMARY, "Barcode ID {} is reserved for the demux output", id);
+    ensure!(loaded.iter().all(|(name, _)| name != id), "Barcode ID {} is used more than once", id);
+    Ok(())
+}
+
+//Index of the barcode found at the ends of the read, if both ends agree.
+//A barcode at only one end is enough unless both_ends is set
+fn classify(fasta: &Fasta, barcodes: &AdapterSet, cmds: &DemuxConfig) -> Option<usize> {
+    let seq = fasta.get_sequence();
+    let len = seq.len();
+    if len == 0 {
+        return None;
+    }
+
+    let start = best_barcode(&barcodes.scores(&seq.slice(0..min(cmds.end_size, len))), cmds);
+    let end = best_barcode(&barcodes.scores(&seq.slice(len.saturating_sub(cmds.end_size)..len)), cmds);
+    match (start, end) {
+        (Some(start), Some(end)) if start == end => Some(start),
+        (Some(barcode), None) | (None, Some(barcode)) if !cmds.both_ends => Some(barcode),
+        _ => None,
+    }
+}
+
+//Best scoring barcode at one end, if it passes the cutoff and beats every other barcode by the margin
+fn best_barcode(scores: &[i32], cmds: &DemuxConfig) -> Option<usize> {
+    let (best, &score) = scores.iter().enumerate().max_by_key(|&(_, score)| score)?;
+    let second = scores
+        .iter()
+        .enumerate()
+        .filter(|&(barcode, _)| barcode != best)
+        .map(|(_, &score)| score)
+        .max()
+        .unwrap_or(i32::MIN);
+
+    if score >= cmds.score_cutoff && score.saturating_sub(second) >= cmds.min_margin {
+        Some(best)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::ScoringConfig, fasta_parsing::reverse_complement};
+
+    //Bases from a fixed LCG, so tests are repeatable
+    fn random_seq(len: usize, seed: u32) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                b"ACGT"[(state >> 16) as usize % 4]
+            })
+            .collect()
+    }
+
+    fn demux_config(both_ends: bool) -> DemuxConfig {
+        DemuxConfig {
+            barcodes_file_path: String::new(),
+            end_size: 100,
+            score_cutoff: 18,
+            min_margin: 5,
+            both_ends,
+            scoring: ScoringConfig::default(),
+        }
+    }
+
+    #[test]
+    fn assigns_reads_by_barcodes_at_either_end() {
+        let (bc01, bc02) = (random_seq(24, 1), random_seq(24, 2));
+        let scoring = AlignScoring::new(&ScoringConfig::default()).unwrap();
+        let barcodes = AdapterSet::new(vec![("bc01".to_owned(), bc01.clone()), ("bc02".to_owned(), bc02.clone())], None, scoring).unwrap();
+        let insert = random_seq(500, 3);
+        let read = |parts: &[&[u8]]| Fasta::new("read".to_owned(), parts.concat().into(), None);
+
+        let start_only = read(&[&bc01, &insert]);
+        let both = read(&[&bc02, &insert, &reverse_complement(&bc02).unwrap()]);
+        let conflicting = read(&[&bc01, &insert, &bc02]);
+        let none = read(&[&insert]);
+
+        let cmds = demux_config(false);
+        assert_eq!(classify(&start_only, &barcodes, &cmds), Some(0));
+        assert_eq!(classify(&both, &barcodes, &cmds), Some(1));
+        assert_eq!(classify(&conflicting, &barcodes, &cmds), None);
+        assert_eq!(classify(&none, &barcodes, &cmds), None);
+
+        let cmds = demux_config(true);
+        assert_eq!(classify(&start_only, &barcodes, &cmds), None);
+        assert_eq!(classify(&both, &barcodes, &cmds), Some(1));
+    }
+
+    #[test]
+    fn best_barcode_needs_cutoff_and_margin() {
+        let cmds = demux_config(false);
+        assert_eq!(best_barcode(&[10, 24, 4], &cmds), Some(1));
+        assert_eq!(best_barcode(&[20, 24], &cmds), None);
+        assert_eq!(best_barcode(&[17, 2], &cmds), None);
+        assert_eq!(best_barcode(&[18], &cmds), Some(0));
+    }
+
+    #[test]
+    fn rejects_unusable_barcode_ids() {
+        let loaded = vec![("bc01".to_owned(), b"ACGT".to_vec())];
+        assert!(check_barcode_id("bc02", &loaded).is_ok());
+        assert!(check_barcode_id("bc01", &loaded).is_err());
+        assert!(check_barcode_id("../bc02", &loaded).is_err());
+        assert!(check_barcode_id("unclassified", &loaded).is_err());
+        assert!(check_barcode_id("summary", &loaded).is_err());
+        assert!(check_barcode_id("", &loaded).is_err());
+    }
+}
@@ -0,0 +1,266 @@
+use std::cmp::{max, min};
+
+use crate::{
+    config::{DirectConfig, WfaConfig},
+    fasta_parsing::{Fasta, Sequence},
+    finder::PalindromeFinder,
+    output::{PalindromeData, RepeatType},
+    packed::PackedSeq,
+    wfa::{calculate_score, wfa_mirrors, Waves},
+};
+
+use anyhow::Result;
+
+//Two copies in a packed sequence, where the first can be at most period long and the second runs to the end
+struct Copies<'a> {
+    seq: &'a PackedSeq,
+    first: usize,
+    period: usize,
+}
+
+impl Copies<'_> {
+    fn first_len(&self) -> usize {
+        self.period
+    }
+
+    fn second_len(&self) -> usize {
+        self.seq.len() - self.first - self.period
+    }
+
+    //Counts matching bases from position x of the first copy and y of the second
+    fn count_equal(&self, x: usize, y: usize) -> usize {
+        self.seq.count_equal(self.first + x, self.first + self.period + y, self.period - x)
+    }
+}
+
+//Best end point of an alignment between two copies
+struct Alignment {
+    x: usize,
+    y: usize,
+    edit_dist: u32,
+    score: f32,
+}
+
+//Forward waves for aligning copies, and palindrome waves for the inverted and mirror searches
+pub struct DirectScratch {
+    wf: Vec<usize>,
+    wf_next: Vec<usize>,
+    waves: Waves,
+}
+
+//Finds direct repeats, along with inverted and mirror repeats if enabled
+impl PalindromeFinder for DirectConfig {
+    type Scratch = DirectScratch;
+
+    fn scratch(&self) -> Result<DirectScratch> {
+        Ok(DirectScratch { wf: Vec::new(), wf_next: Vec::new(), waves: self.wfa.scratch()? })
+    }
+
+    fn find(&self, fasta: &Fasta, output: &mut Vec<PalindromeData>, scratch: &mut DirectScratch) -> Result<()> {
+        let first = output.len();
+        find_direct(fasta.get_sequence(), fasta.get_id(), output, self, scratch)?;
+
+        if self.inverted {
+            self.wfa.find(fasta, output, &mut scratch.waves)?;
+        }
+        if self.mirror {
+            wfa_mirrors(fasta, output, &self.wfa, &mut scratch.waves)?;
+        }
+        output[first..].sort_by_key(|repeat| (repeat.get_start(), repeat.get_end()));
+        Ok(())
+    }
+}
+
+//Finds pairs of copies, skipping past each pair once it is reported. Tandem arrays of more than two copies
+//are only reported as their best scoring pair, so copies left over after it are not reported
+fn find_direct(
+    seq: &Sequence,
+    name: &str,
+    output: &mut Vec<PalindromeData>,
+    direct_args: &DirectConfig,
+    scratch: &mut DirectScratch,
+) -> Result<()> {
+    let wfa_args = &direct_args.wfa;
+
+    let packed_seq = PackedSeq::new(seq)?;
+
+    let len = packed_seq.len();
+    let max_period = direct_args.max_arm + wfa_args.gap_len;
+    let seed = min(wfa_args.min_length, 8);
+    let mut index = 0;
+
+    while index + 2 * wfa_args.min_length <= len {
+        let mut best: Option<(usize, Alignment)> = None;
+
+        //The period is the distance between the starts of the two copies
+        for period in wfa_args.min_length..=max_period {
+            if index + period + seed > len {
+                break;
+            }
+            if packed_seq.count_equal(index, index + period, seed) < seed {
+                continue;
+            }
+
+            let copies = Copies { seq: &packed_seq, first: index, period };
+            let alignment = align_copies(&copies, wfa_args, scratch);
+            if let Some(alignment) = alignment {
+                if best.as_ref().is_none_or(|(_, best)| alignment.score > best.score) {
+                    best = Some((period, alignment));
+                }
+            }
+        }
+
+        let Some((period, alignment)) = best else {
+            index += 1;
+            continue;
+        };
+
+        let mut repeat = PalindromeData::new(
+            index as u32,
+            (index + period + alignment.y - 1) as u32,
+            alignment.y as u32,
+            (period - alignment.x) as u32,
+            (period + alignment.y) as u32,
+            alignment.edit_dist,
+            name.to_owned(),
+            seq.slice(index..index + period + alignment.y).into_owned(),
+        );
+        repeat.set_repeat_type(RepeatType::Direct);
+        output.push(repeat);
+
+        index += period + alignment.y;
+    }
+    Ok(())
+}
+
+//Aligns the first copy against the sequence following it with a forward WFA, returning the best scoring end point
+fn align_copies(copies: &Copies, wfa_args: &WfaConfig, scratch: &mut DirectScratch) -> Option<Alignment> {
+    let DirectScratch { wf, wf_next, .. } = scratch;
+    wf.clear();
+    wf.push(copies.count_equal(0, 0));
+
+    let mut best: Option<Alignment> = None;
+    let mut max_score = f32::MIN;
+    let mut edit_dist = 0;
+
+    loop {
+        let mut max_wf_score = f32::MIN;
+        let mut max_x = 0;
+        let mut at_end = false;
+
+        for (i, &x) in wf.iter().enumerate() {
+            let y = (x + i) - edit_dist as usize;
+            let score = calculate_score(x, y, edit_dist, wfa_args);
+            max_wf_score = f32::max(max_wf_score, score);
+            max_x = max(max_x, x);
+
+            let spacer = copies.first_len() - x;
+            if spacer <= wfa_args.gap_len
+                && x >= wfa_args.min_length
+                && (edit_dist as f32) / (x as f32) <= wfa_args.mismatch_proportion
+                && best.as_ref().is_none_or(|best| score > best.score)
+            {
+                best = Some(Alignment { x, y, edit_dist, score });
+            }
+
+            if x == copies.first_len() || y == copies.second_len() {
+                at_end = true;
+            }
+        }
+
+        max_score = f32::max(max_score, max_wf_score);
+
+        //Same pruning as the palindrome search, and stops before the diagonals outgrow the sequences
+        if at_end
+            || edit_dist as usize + 1 >= min(copies.first_len(), copies.second_len())
+            || (edit_dist as f32) / (max_x as f32 + 0.001) > wfa_args.mismatch_proportion
+            || max_wf_score < max_score - f32::max(wfa_args.x_drop, (max_x as f32) * 0.1)
+        {
+            break;
+        }
+
+        next_wave(wf, wf_next, edit_dist, copies);
+        std::mem::swap(wf, wf_next);
+        edit_dist += 1;
+    }
+    best
+}
+
+//Computes the next wave, where wf[i] is the furthest x on diagonal y - x = i - edit_dist
+fn next_wave(wf: &[usize], wf_next: &mut Vec<usize>, edit_dist: u32, copies: &Copies) {
+    let diagonals = wf.len() + 2;
+    let offset = edit_dist as isize + 1;
+    wf_next.clear();
+
+    for i in 0..diagonals {
+        let k = i as isize - offset;
+
+        //Mismatch stays on the diagonal, gaps move to the neighbouring ones
+        let mut x = 0;
+        if i >= 1 && i - 1 < wf.len() {
+            x = max(x, wf[i - 1] + 1);
+        }
+        if i >= 2 {
+            x = max(x, wf[i - 2]);
+        }
+        if i < wf.len() {
+            x = max(x, wf[i] + 1);
+        }
+
+        let upper = min(copies.first_len() as isize, copies.second_len() as isize - k);
+        let x = max(min(x as isize, upper), max(-k, 0)) as usize;
+        let y = (x as isize + k) as usize;
+
+        if x < copies.first_len() && y < copies.second_len() {
+            wf_next.push(x + copies.count_equal(x, y));
+        } else {
+            wf_next.push(x);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::find_palindromes;
+
+    //Bases from a fixed LCG, so tests are repeatable
+    fn random_seq(len: usize, seed: u32) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                b"ACGT"[(state >> 16) as usize % 4]
+            })
+            .collect()
+    }
+
+    fn direct_config() -> DirectConfig {
+        DirectConfig { wfa: WfaConfig { min_length: 10, ..Default::default() }, ..Default::default() }
+    }
+
+    #[test]
+    fn finds_two_copies_with_a_gap() {
+        let unit = random_seq(30, 4);
+        let seq = [random_seq(50, 1), unit.clone(), b"GAT".to_vec(), unit, random_seq(50, 2)].concat();
+
+        let repeats: Vec<_> = find_palindromes(&seq, &direct_config()).unwrap().collect();
+        assert_eq!(repeats.len(), 1);
+        let repeat = &repeats[0];
+        assert_eq!((repeat.get_start(), repeat.get_end()), (50, 112));
+        assert_eq!((repeat.get_arm_length(), repeat.get_gap(), repeat.get_length()), (30, 3, 63));
+        assert_eq!(repeat.get_repeat_type(), RepeatType::Direct);
+    }
+
+    #[test]
+    fn tandem_arrays_are_reported_as_two_copies() {
+        let unit = random_seq(30, 4);
+        let seq = [random_seq(50, 1), unit.repeat(3), random_seq(50, 2)].concat();
+
+        //Only the first two copies are reported, and the third has nothing left to pair with
+        let repeats: Vec<_> = find_palindromes(&seq, &direct_config()).unwrap().collect();
+        assert_eq!(repeats.len(), 1);
+        assert_eq!((repeats[0].get_start(), repeats[0].get_end()), (50, 109));
+        assert_eq!((repeats[0].get_arm_length(), repeats[0].get_gap()), (30, 0));
+    }
+}
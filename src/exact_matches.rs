@@ -1,20 +1,54 @@
-use anyhow::{bail, ensure, Ok, Result};
+use anyhow::{bail, Ok, Result};
 
 use crate::{
-    command_line::FixedArgs,
-    fasta_parsing::Fasta,
+    config::FixedConfig,
+    fasta_parsing::{Fasta, Sequence},
+    finder::PalindromeFinder,
     output::PalindromeData,
 };
 
-pub static PALINDROME_LENGTH: u32 = 5;
-pub static GAP_LENGTH: u32 = 2;
+const INVALID: u8 = u8::MAX;
 
-pub fn fixed_match(
-    fasta: Fasta,
+//Codes for each byte, where A = 0, C = 1, G = 2, T = 3, N = 4
+const BASE_CODES: [u8; 256] = {
+    let mut codes = [INVALID; 256];
+    codes[b'A' as usize] = 0;
+    codes[b'a' as usize] = 0;
+    codes[b'C' as usize] = 1;
+    codes[b'c' as usize] = 1;
+    codes[b'G' as usize] = 2;
+    codes[b'g' as usize] = 2;
+    codes[b'T' as usize] = 3;
+    codes[b't' as usize] = 3;
+    codes[b'N' as usize] = 4;
+    codes[b'n' as usize] = 4;
+    codes
+};
+
+//Complement of each code, where N maps to a code no base has so it never matches
+const COMPLEMENTS: [u8; 5] = [3, 2, 1, 0, 5];
+
+//The scratch buffer holds the encoded sequence
+impl PalindromeFinder for FixedConfig {
+    type Scratch = Vec<u8>;
+
+    fn scratch(&self) -> Result<Vec<u8>> {
+        Ok(Vec::new())
+    }
+
+    fn find(&self, fasta: &Fasta, output: &mut Vec<PalindromeData>, codes: &mut Vec<u8>) -> Result<()> {
+        fixed_match(fasta, output, self, codes)
+    }
+}
+
+fn fixed_match(
+    fasta: &Fasta,
     output: &mut Vec<PalindromeData>,
-    cmds: &FixedArgs,
+    cmds: &FixedConfig,
+    codes: &mut Vec<u8>,
 ) -> Result<()> {
     let seq = fasta.get_sequence();
+    encode(seq, codes)?;
     let mut i = 0;
     while i < seq.len() as u32 {
 
@@ -23,8 +57,8 @@ pub fn fixed_match(
 
         while i >= j && j <= (cmds.gap_len + 1) as u32 {
             
-            let length = count_palindrome(i, i + j, seq, cmds.mismatches)?;
-            if length >= cmds.len as u32 {
+            let length = count_palindrome(i, i + j, codes, cmds.mismatches);
+            if length >= cmds.min_length as u32 {
                 let palin = PalindromeData::new(
                     i + 1 - length,
                     i + length + j - 1,
@@ -32,8 +66,8 @@ pub fn fixed_match(
                     j - 1,
                     2 * length + j - 1,
                     cmds.mismatches,
-                    fasta.get_name().to_owned(),
-                    seq[(i + 1 - length) as usize..(i + length + j) as usize].to_owned(),
+                    fasta.get_id().to_owned(),
+                    seq.slice((i + 1 - length) as usize..(i + length + j) as usize).into_owned(),
                 );
                 output.push(palin);
                 increment = length + j;
@@ -47,46 +81,73 @@ pub fn fixed_match(
     Ok(())
 }
 
-fn count_palindrome(start: u32, end: u32, seq: &str, mismatch: u32) -> Result<u32> {
-    let mut prev: i32 = start as i32;
-    let mut next = end as usize;
+fn encode(seq: &Sequence, codes: &mut Vec<u8>) -> Result<()> {
+    codes.clear();
+    for &base in seq.lines(0..seq.len()).flatten() {
+        match BASE_CODES[base as usize] {
+            INVALID => bail!("Not a base pair - check format"),
+            code => codes.push(code),
+        }
+    }
+    Ok(())
+}
+
+fn count_palindrome(start: u32, end: u32, codes: &[u8], mismatch: u32) -> u32 {
+    let left = codes[..=start as usize].iter().rev();
+    let right = codes.get(end as usize..).unwrap_or_default().iter();
     let mut count = 0;
     let mut mismatches = 0;
 
-    while prev >= 0 && next < seq.len() {
-        if !is_complement(&seq[prev as usize..=prev as usize], &seq[next..=next])? {
+    for (&base1, &base2) in left.zip(right) {
+        if COMPLEMENTS[base1 as usize] != base2 {
             mismatches += 1;
         }
         if mismatches > mismatch {
             break;
         }
-
         count += 1;
-        prev -= 1;
-        next += 1;
     }
 
-    Ok(count)
+    count
 }
 
-pub fn get_complement(bp: &str) -> Result<&str> {
-    ensure!(
-        bp.len() == 1,
-        "Cannot get complement of non-base pair string"
-    );
-
-    let bpu = bp.to_uppercase();
-
-    let value = match &bpu[0..=0] {
-        "A" => "T",
-        "T" => "A",
-        "C" => "G",
-        "G" => "C",
-        _ => bail!("Not a base pair - check format"),
-    };
-    Ok(value)
-}
-pub fn is_complement(base1: &str, base2: &str) -> Result<bool> {
-    let is_equal = get_complement(base1)? == base2.to_uppercase();
-    Ok(is_equal)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::find_palindromes;
+
+    fn exact() -> FixedConfig {
+        FixedConfig { min_length: 6, gap_len: 3, mismatches: 0 }
+    }
+
+    fn coordinates(seq: &[u8], config: &FixedConfig) -> Vec<(u32, u32, u32, u32)> {
+        find_palindromes(seq, config)
+            .unwrap()
+            .map(|palin| (palin.get_start(), palin.get_end(), palin.get_arm_length(), palin.get_gap()))
+            .collect()
+    }
+
+    #[test]
+    fn finds_palindromes_in_either_case() {
+        //Arms of ACGGTC and GACCGT around a GA spacer
+        let seq = b"TTTTACGGTCGAGACCGTTTTT";
+        assert_eq!(coordinates(seq, &exact()), [(4, 17, 6, 2)]);
+        assert_eq!(coordinates(&seq.to_ascii_lowercase(), &exact()), [(4, 17, 6, 2)]);
+
+        let palin = find_palindromes(b"ttttACGGTCgaGACCGTtttt", &exact()).unwrap().next().unwrap();
+        assert_eq!(palin.get_sequence(), b"ACGGTCgaGACCGT");
+    }
+
+    #[test]
+    fn n_never_matches() {
+        assert!(coordinates(b"NNNNNNNNNNNNNNNN", &exact()).is_empty());
+        //With mismatches allowed, an N in an arm is counted as one
+        let config = FixedConfig { mismatches: 1, ..exact() };
+        assert_eq!(coordinates(b"GGGGACNGTCGAGACCGTGGGG", &config), [(4, 17, 6, 2)]);
+    }
+
+    #[test]
+    fn rejects_other_bytes() {
+        assert!(find_palindromes(b"ACGUACGU", &exact()).is_err());
+    }
 }
@@ -1,77 +1,413 @@
-use crate::command_line::PalinArgs;
+use crate::config::HeaderSplit;
 use crate::output::BUFF_SIZE;
-use anyhow::{anyhow, bail, Ok, Result};
-use flate2::read::MultiGzDecoder;
+use anyhow::{anyhow, bail, ensure, Ok, Result};
+use flate2::{read::MultiGzDecoder, write::GzEncoder, Compression};
+use memmap2::Mmap;
 use std::{
+    borrow::Cow,
+    cmp::min,
+    fmt::Display,
     fs::File,
-    io::{BufRead, BufReader, Lines, Read},
+    io::{BufRead, BufReader, BufWriter, Read, Write},
     mem,
+    ops::Range,
+    sync::Arc,
 };
 
+//Added to Phred scores in FASTQ quality strings
+pub const PHRED_OFFSET: u8 = 33;
+
 #[derive(Debug, Clone)]
 pub struct Fasta {
-    pub name: String,
-    pub sequence: String,
+    //Full header, made of the ID and an optional description
+    name: String,
+    sequence: Sequence,
+    //Quality string of reads parsed from FASTQ, always the same length as the sequence
+    quality: Option<Vec<u8>>,
+    //Length of the ID at the start of name, kept up to date whenever name or header_split changes
+    header_split: HeaderSplit,
+    id_len: usize,
+}
+
+//Bases of a record, either read into memory or borrowed from a memory-mapped file
+#[derive(Debug, Clone)]
+pub enum Sequence {
+    Owned(Vec<u8>),
+    Mapped(MappedSequence),
 }
 
+//Sequence in a memory-mapped FASTA file, where every line but the last holds line_bases bases
 #[derive(Debug, Clone)]
-pub struct Fastq {
-    pub id: String,
-    pub sequence: String,
-    pub quality: String,
+pub struct MappedSequence {
+    map: Arc<Mmap>,
+    offset: usize,
+    len: usize,
+    line_bases: usize,
+    line_bytes: usize,
 }
 
-impl Fastq{
-    pub fn new(id: String, sequence: String, quality: String) -> Self{
-        Self{id, sequence, quality}
+impl Sequence {
+    pub fn len(&self) -> usize {
+        match self {
+            Sequence::Owned(seq) => seq.len(),
+            Sequence::Mapped(seq) => seq.len,
+        }
     }
-    pub fn get_sequence(&self) -> &str {
-        &self.sequence
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
     }
-    pub fn get_id(&self) -> &str {
-        &self.id
+
+    //Lines of the bases in range, without line endings
+    pub fn lines(&self, range: Range<usize>) -> SequenceLines<'_> {
+        match self {
+            Sequence::Owned(seq) => SequenceLines {
+                data: seq,
+                pos: range.start,
+                end: range.end,
+                offset: 0,
+                line_bases: usize::MAX,
+                line_bytes: 0,
+            },
+            Sequence::Mapped(seq) => SequenceLines {
+                data: &seq.map,
+                pos: range.start,
+                end: range.end,
+                offset: seq.offset,
+                line_bases: seq.line_bases,
+                line_bytes: seq.line_bytes,
+            },
+        }
+    }
+
+    //Bases in range, only copied if they span more than one line
+    pub fn slice(&self, range: Range<usize>) -> Cow<'_, [u8]> {
+        let mut lines = self.lines(range.clone());
+        let first = lines.next().unwrap_or_default();
+        let Some(second) = lines.next() else {
+            return Cow::Borrowed(first);
+        };
+
+        let mut bases = Vec::with_capacity(range.len());
+        bases.extend_from_slice(first);
+        bases.extend_from_slice(second);
+        lines.for_each(|line| bases.extend_from_slice(line));
+        Cow::Owned(bases)
+    }
+
+    pub fn to_contiguous(&self) -> Cow<'_, [u8]> {
+        self.slice(0..self.len())
+    }
+
+    //Copies a mapped sequence into memory so it can be modified
+    pub(crate) fn to_mut(&mut self) -> &mut Vec<u8> {
+        if let Sequence::Mapped(_) = self {
+            *self = Sequence::Owned(self.to_contiguous().into_owned());
+        }
+        match self {
+            Sequence::Owned(seq) => seq,
+            Sequence::Mapped(_) => unreachable!(),
+        }
     }
-    pub fn get_quality(&self) -> &str {
-        &self.quality
+}
+
+impl From<Vec<u8>> for Sequence {
+    fn from(seq: Vec<u8>) -> Self {
+        Sequence::Owned(seq)
     }
-    pub fn to_fasta(self) -> Fasta{
-        Fasta { name: self.id, sequence: self.sequence}
+}
+
+pub struct SequenceLines<'a> {
+    data: &'a [u8],
+    pos: usize,
+    end: usize,
+    offset: usize,
+    line_bases: usize,
+    line_bytes: usize,
+}
+
+impl<'a> Iterator for SequenceLines<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos >= self.end {
+            return None;
+        }
+        let line = self.pos / self.line_bases;
+        let column = self.pos % self.line_bases;
+        let len = min(self.line_bases - column, self.end - self.pos);
+
+        let start = self.offset + line * self.line_bytes + column;
+        self.pos += len;
+        Some(&self.data[start..start + len])
     }
 }
 
 impl Fasta {
-    pub fn new(name: String, sequence: String) -> Self {
-        Self { name, sequence }
+    //The ID is split from the header at the first space or tab, until set_header_split is called.
+    //Panics if the quality isn't as long as the sequence
+    pub fn new(name: String, sequence: Sequence, quality: Option<Vec<u8>>) -> Self {
+        assert!(quality.as_ref().is_none_or(|quality| quality.len() == sequence.len()), "Quality must be as long as the sequence");
+        let header_split = HeaderSplit::default();
+        let id_len = header_split.id_len(&name);
+        Self { name, sequence, quality, header_split, id_len }
     }
-    pub fn get_sequence(&self) -> &str {
+    pub fn get_sequence(&self) -> &Sequence {
         &self.sequence
     }
     pub fn get_name(&self) -> &str {
         &self.name
     }
-    pub fn set_sequence(&mut self, seq: String){
-        self.sequence = seq;
+    pub fn get_id(&self) -> &str {
+        &self.name[..self.id_len]
+    }
+    pub fn get_description(&self) -> Option<&str> {
+        let description = self.name[self.id_len..].trim_start();
+        (!description.is_empty()).then_some(description)
+    }
+
+    pub fn set_header_split(&mut self, header_split: HeaderSplit) {
+        self.header_split = header_split;
+        self.id_len = header_split.id_len(&self.name);
+    }
+
+    //Replaces the whole header, splitting the ID from it the same way as before
+    pub fn set_name(&mut self, name: String) {
+        self.id_len = self.header_split.id_len(&name);
+        self.name = name;
+    }
+
+    //Replaces the ID, keeping the description
+    pub fn set_id(&mut self, id: &str) {
+        self.name.replace_range(..self.id_len, id);
+        self.id_len = id.len();
+    }
+    pub fn get_quality(&self) -> Option<&[u8]> {
+        self.quality.as_deref()
+    }
+
+    //Bases that can be changed in place, copying a mapped sequence into memory first.
+    //The length can't change, so the quality still matches
+    pub fn bases_mut(&mut self) -> &mut [u8] {
+        self.sequence.to_mut()
+    }
+
+    //Replaces the sequence along with its quality, which must be as long as it
+    pub fn set_sequence(&mut self, sequence: Sequence, quality: Option<Vec<u8>>) -> Result<()> {
+        ensure!(
+            quality.as_ref().is_none_or(|quality| quality.len() == sequence.len()),
+            "Quality of {} must be as long as its sequence", self.get_id()
+        );
+        self.sequence = sequence;
+        self.quality = quality;
+        Ok(())
+    }
+
+    //Keeps only the bases in range, along with their qualities
+    pub fn trim(&mut self, range: Range<usize>) {
+        self.sequence = self.sequence.slice(range.clone()).into_owned().into();
+        if let Some(quality) = &mut self.quality {
+            quality.truncate(range.end);
+            quality.drain(..range.start);
+        }
+    }
+
+    //Replaces bases with a Phred quality below min_quality with N, if the read has qualities
+    pub fn mask_low_quality(&mut self, min_quality: u8) {
+        let Some(quality) = &self.quality else {
+            return;
+        };
+        let seq = self.sequence.to_mut();
+        for (base, &score) in seq.iter_mut().zip(quality) {
+            if score.saturating_sub(PHRED_OFFSET) < min_quality {
+                *base = b'N';
+            }
+        }
+    }
+
+    pub fn reverse_complement(&mut self) -> Result<()> {
+        self.sequence = reverse_complement(&self.sequence.to_contiguous())?.into();
+        if let Some(quality) = &mut self.quality {
+            quality.reverse();
+        }
+        Ok(())
+    }
+}
+
+//Reads the next line into buf without its line ending, returning false at the end of the input
+fn read_line<T: Read>(reader: &mut BufReader<T>, buf: &mut Vec<u8>) -> Result<bool> {
+    buf.clear();
+    if reader.read_until(b'\n', buf).map_err(|err| anyhow!("Invalid line/file format: {err}"))? == 0 {
+        return Ok(false);
+    }
+    if buf.last() == Some(&b'\n') {
+        buf.pop();
     }
+    if buf.last() == Some(&b'\r') {
+        buf.pop();
+    }
+    Ok(true)
+}
+
+fn parse_name(line: &[u8]) -> Result<String> {
+    String::from_utf8(line[1..].to_vec()).map_err(|err| anyhow!("Invalid line/file format: {err}"))
 }
 
 #[derive(Debug)]
-pub struct FastaIterator<T: Read> {
-    lines_reader: Lines<BufReader<T>>,
+pub(crate) struct FastaIterator<T: Read> {
+    reader: BufReader<T>,
+    line: Vec<u8>,
     curr_name: String,
+    //Length of the previous sequence, used to size the next one
+    capacity: usize,
 }
 
-pub struct FastqIterator<T:Read> {
-    lines_reader: Lines<BufReader<T>>,
-    curr_name: String,
-    counter: i32,
+pub(crate) struct FastqIterator<T:Read> {
+    reader: BufReader<T>,
+    line: Vec<u8>,
+    line_number: usize,
+    //Set after a bad record, so lines are skipped until the next header
+    recovering: bool,
+    finished: bool,
+    //Splits the ID from the header, which the '+' line may repeat instead of the whole header
+    header_split: HeaderSplit,
+}
+
+//A structural problem in a FASTQ file, returned as the error of the record it was found in
+#[derive(Debug, Clone, PartialEq)]
+pub struct FastqProblem {
+    pub line: usize,
+    //Header of the read, unless the problem is in the header itself
+    pub read: Option<String>,
+    pub message: String,
+}
+
+impl Display for FastqProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for FastqProblem {}
+
+//Every problem found in a FASTQ file, along with the number of reads without problems
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ValidationReport {
+    pub reads: usize,
+    pub problems: Vec<FastqProblem>,
+}
+
+//Part of the record the parser expects next
+enum FastqState {
+    Header,
+    Sequence,
+    Quality,
 }
 
 impl<T: Read> FastqIterator<T> {
     pub fn new(bufreader: BufReader<T>) -> Self {
         Self {
-            lines_reader: bufreader.lines(),
-            curr_name: String::new(),
-            counter: 0,
+            reader: bufreader,
+            line: Vec::new(),
+            line_number: 0,
+            recovering: false,
+            finished: false,
+            header_split: HeaderSplit::default(),
+        }
+    }
+
+    pub fn with_header_split(mut self, header_split: HeaderSplit) -> Self {
+        self.header_split = header_split;
+        self
+    }
+
+    fn read_line(&mut self) -> Result<bool> {
+        let read = read_line(&mut self.reader, &mut self.line);
+        match read {
+            Result::Ok(true) => self.line_number += 1,
+            Result::Ok(false) => {}
+            Err(_) => self.finished = true,
+        }
+        read
+    }
+
+    //A problem at the current line, in the read with header name if its header has been read
+    fn problem(&self, name: &str, message: String) -> anyhow::Error {
+        let read = (!name.is_empty()).then(|| name.to_owned());
+        FastqProblem { line: self.line_number, read, message }.into()
+    }
+
+    //Sequences can span several lines up to the '+' line, and the quality is read until it is as long as the sequence,
+    //so quality lines starting with '@' or '+' aren't mistaken for the next record. Blank lines are skipped
+    fn next_record(&mut self) -> Result<Option<Fasta>> {
+        let mut state = FastqState::Header;
+        let mut name = String::new();
+        let mut seq = Vec::new();
+        let mut quality = Vec::new();
+
+        loop {
+            if !self.read_line()? {
+                self.finished = true;
+                return match state {
+                    FastqState::Header => Ok(None),
+                    FastqState::Sequence => Err(self.problem(&name, format!("{} ends without a '+' line", name))),
+                    FastqState::Quality => Err(self.problem(
+                        &name,
+                        format!("quality of {} is shorter than its sequence ({} vs {})", name, quality.len(), seq.len()),
+                    )),
+                };
+            }
+            let line = &self.line;
+
+            match state {
+                FastqState::Header => {
+                    if line.is_empty() || (self.recovering && !line.starts_with(b"@")) {
+                        continue;
+                    }
+                    self.recovering = false;
+                    if !line.starts_with(b"@") {
+                        return Err(self.problem("", "expected a header starting with '@'".to_owned()));
+                    }
+                    name = parse_name(line).map_err(|err| self.problem("", err.to_string()))?;
+                    state = FastqState::Sequence;
+                }
+                FastqState::Sequence => {
+                    if let Some(repeated) = line.strip_prefix(b"+") {
+                        let id = &name[..self.header_split.id_len(&name)];
+                        if !repeated.is_empty() && repeated != name.as_bytes() && repeated != id.as_bytes() {
+                            return Err(self.problem(&name, format!("'+' line doesn't match the header of {}", name)));
+                        }
+                        //Empty reads are skipped like empty FASTA records
+                        if seq.is_empty() {
+                            state = FastqState::Header;
+                        } else {
+                            state = FastqState::Quality;
+                        }
+                        continue;
+                    }
+                    if let Some(&base) = line.iter().find(|base| !base.is_ascii_alphabetic()) {
+                        return Err(self.problem(&name, format!("invalid base '{}' in {}", base.escape_ascii(), name)));
+                    }
+                    seq.extend_from_slice(line);
+                }
+                FastqState::Quality => {
+                    if let Some(&score) = line.iter().find(|score| !(b'!'..=b'~').contains(*score)) {
+                        return Err(self.problem(&name, format!("invalid quality character '{}' in {}", score.escape_ascii(), name)));
+                    }
+                    quality.extend_from_slice(line);
+                    if quality.len() > seq.len() {
+                        return Err(self.problem(
+                            &name,
+                            format!("quality of {} is longer than its sequence ({} vs {})", name, quality.len(), seq.len()),
+                        ));
+                    }
+                    if quality.len() == seq.len() {
+                        let mut fasta = Fasta::new(name, seq.into(), Some(quality));
+                        fasta.set_header_split(self.header_split);
+                        return Ok(Some(fasta));
+                    }
+                }
+            }
         }
     }
 }
@@ -80,48 +416,53 @@ impl<T: Read> Iterator for FastqIterator<T>{
     type Item = Result<Fasta>;
 
     fn next(&mut self) -> Option<Self::Item>{
-        let mut seq: String = String::new();
+        if self.finished {
+            return None;
+        }
+        let record = self.next_record();
+        if record.is_err() {
+            self.recovering = true;
+        }
+        record.transpose()
+    }
+}
 
-        for line in self.lines_reader.by_ref(){
-            let line = match line{
-                Result::Ok(line) => line,
-                Err(err) => return Some(Err(anyhow!("Invalid line/file format: {err}")))
-            };
+impl<T: Read> FastaIterator<T> {
+    pub fn new(bufreader: BufReader<T>) -> Self {
+        Self {
+            reader: bufreader,
+            line: Vec::new(),
+            curr_name: String::new(),
+            capacity: 0,
+        }
+    }
+
+    fn next_record(&mut self) -> Result<Option<Fasta>> {
+        let mut seq: Vec<u8> = Vec::with_capacity(self.capacity);
 
-            if self.counter == 0 && line.starts_with('@'){
-                self.counter += 1;
-                let mut name: String = line.strip_prefix('@').unwrap().to_owned();
-                if seq.is_empty(){
-                    name.clone_into(&mut self.curr_name);
+        while read_line(&mut self.reader, &mut self.line)? {
+            let line = &self.line;
+
+            if line.starts_with(b">") {
+                let mut name = parse_name(line)?;
+                if seq.is_empty() {
+                    self.curr_name = name;
                     continue;
                 }
-                
                 mem::swap(&mut name, &mut self.curr_name);
-                return Some(Ok(Fasta {
-                    name, 
-                    sequence: seq
-                }))
-            } else if self.counter == 1{
-                seq = line;
-                self.counter += 1;
-                continue
-            } else if self.counter == 2{
-                self.counter += 1;
-                continue
-            } else if self.counter == 3{
-                self.counter = 0;
-                continue
+                self.capacity = seq.len();
+                return Ok(Some(Fasta::new(name, seq.into(), None)));
+            //Checks for valid starting line in fasta
+            } else if !self.curr_name.is_empty() {
+                seq.extend_from_slice(line);
             } else {
-                return Some(Err(anyhow!("Invalid fastq format")));
+                bail!("Invalid fasta format");
             }
         }
         if seq.is_empty() {
-            None
+            Ok(None)
         } else {
-            Some(Ok(Fasta {
-                name: mem::take(&mut self.curr_name),
-                sequence: seq,
-            }))
+            Ok(Some(Fasta::new(mem::take(&mut self.curr_name), seq.into(), None)))
         }
     }
 }
@@ -130,73 +471,562 @@ impl<T: Read> Iterator for FastaIterator<T> {
     type Item = Result<Fasta>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let mut seq: String = String::new();
+        self.next_record().transpose()
+    }
+}
 
-        for line in self.lines_reader.by_ref() {
-            let line = match line {
-                Result::Ok(line) => line,
-                Err(err) => return Some(Err(anyhow!("Invalid line/file format: {err}"))),
-            };
+//Reads an uncompressed FASTA file through a memory map, so sequences with a fixed line width are never copied
+pub(crate) struct MappedFastaIterator {
+    map: Arc<Mmap>,
+    pos: usize,
+}
 
-            if line.starts_with('>') {
-                let mut name = line.strip_prefix('>').unwrap().to_owned();
-                if seq.is_empty() {
-                    name.clone_into(&mut self.curr_name);
-                    continue;
+impl MappedFastaIterator {
+    pub fn open(file_name: &str) -> Result<Self> {
+        let file = File::open(file_name)?;
+        //The file must not be modified while it's mapped
+        let map = unsafe { Mmap::map(&file)? };
+        Ok(Self { map: Arc::new(map), pos: 0 })
+    }
+
+    //Next line as (start, content end, next line start)
+    fn next_line(&self, pos: usize) -> (usize, usize, usize) {
+        let data = &self.map[pos..];
+        let (mut end, next) = match data.iter().position(|&b| b == b'\n') {
+            Some(newline) => (pos + newline, pos + newline + 1),
+            None => (self.map.len(), self.map.len()),
+        };
+        if end > pos && self.map[end - 1] == b'\r' {
+            end -= 1;
+        }
+        (pos, end, next)
+    }
+
+    fn next_record(&mut self) -> Result<Option<Fasta>> {
+        loop {
+            if self.pos >= self.map.len() {
+                return Ok(None);
+            }
+            let (start, end, next) = self.next_line(self.pos);
+            if !self.map[start..end].starts_with(b">") {
+                bail!("Invalid fasta format");
+            }
+            let name = parse_name(&self.map[start..end])?;
+            self.pos = next;
+
+            let mut offset = self.pos;
+            let mut len = 0;
+            let mut line_bases = 0;
+            let mut line_bytes = 0;
+            let mut last_line = false;
+            let mut uniform = true;
+
+            while self.pos < self.map.len() && self.map[self.pos] != b'>' {
+                let (start, end, next) = self.next_line(self.pos);
+                let bases = end - start;
+                if bases > 0 {
+                    //Every line must be as long as the first one, except for the last, and end the same way
+                    if line_bases == 0 {
+                        (offset, line_bases, line_bytes) = (start, bases, next - start);
+                    } else if last_line || bases > line_bases || (next - end != line_bytes - line_bases && next != self.map.len()) {
+                        uniform = false;
+                    }
+                    len += bases;
                 }
-                mem::swap(&mut name, &mut self.curr_name);
-                return Some(Ok(Fasta {
-                    name,
-                    sequence: seq,
-                }));
-            //Checks for valid starting line in fasta
-            } else if !self.curr_name.is_empty() {
-                seq += &line;
+                last_line |= bases < line_bases;
+                self.pos = next;
+            }
+
+            //Skips records without a sequence, the same as FastaIterator
+            if len == 0 {
+                continue;
+            }
+            let sequence = if uniform {
+                Sequence::Mapped(MappedSequence { map: Arc::clone(&self.map), offset, len, line_bases, line_bytes })
             } else {
-                return Some(Err(anyhow!("Invalid fasta format")));
+                Sequence::Owned(self.copy_lines(offset, len))
+            };
+            return Ok(Some(Fasta::new(name, sequence, None)));
+        }
+    }
+
+    //Concatenates the lines of a record that can't be read in place
+    fn copy_lines(&self, mut pos: usize, len: usize) -> Vec<u8> {
+        let mut seq = Vec::with_capacity(len);
+        while seq.len() < len {
+            let (start, end, next) = self.next_line(pos);
+            seq.extend_from_slice(&self.map[start..end]);
+            pos = next;
+        }
+        seq
+    }
+}
+
+impl Iterator for MappedFastaIterator {
+    type Item = Result<Fasta>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_record().transpose()
+    }
+}
+
+pub struct FastaWriter<T: Write> {
+    writer: T,
+    line_width: usize,
+}
+
+impl<T: Write> FastaWriter<T> {
+    ///A line width of 0 writes every sequence on a single line
+    pub fn new(writer: T, line_width: usize) -> Self {
+        Self { writer, line_width }
+    }
+
+    pub fn write(&mut self, fasta: &Fasta) -> Result<()> {
+        let seq = fasta.get_sequence();
+        self.write_lines(fasta.get_name(), seq.lines(0..seq.len()))
+    }
+
+    pub fn write_record(&mut self, name: &str, seq: &[u8]) -> Result<()> {
+        self.write_lines(name, [seq].into_iter())
+    }
+
+    //Writes the bases of the input lines rewrapped to the line width
+    fn write_lines<'a>(&mut self, name: &str, lines: impl Iterator<Item = &'a [u8]>) -> Result<()> {
+        writeln!(self.writer, ">{}", name)?;
+        if self.line_width == 0 {
+            for line in lines {
+                self.writer.write_all(line)?;
             }
+            self.writer.write_all(b"\n")?;
+            return Ok(());
         }
-        if seq.is_empty() {
-            None
+
+        let mut column = 0;
+        for mut line in lines {
+            while !line.is_empty() {
+                let len = min(self.line_width - column, line.len());
+                self.writer.write_all(&line[..len])?;
+                line = &line[len..];
+                column += len;
+                if column == self.line_width {
+                    self.writer.write_all(b"\n")?;
+                    column = 0;
+                }
+            }
+        }
+        if column > 0 {
+            self.writer.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+pub struct FastqWriter<T: Write> {
+    writer: T,
+}
+
+impl<T: Write> FastqWriter<T> {
+    pub fn new(writer: T) -> Self {
+        Self { writer }
+    }
+
+    pub fn write(&mut self, fasta: &Fasta) -> Result<()> {
+        let quality = fasta.get_quality().ok_or_else(|| anyhow!("Missing quality for {}", fasta.get_name()))?;
+        let seq = fasta.get_sequence();
+
+        writeln!(self.writer, "@{}", fasta.get_name())?;
+        for line in seq.lines(0..seq.len()) {
+            self.writer.write_all(line)?;
+        }
+        self.writer.write_all(b"\n+\n")?;
+        self.writer.write_all(quality)?;
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+//Writes reads as FASTQ when their qualities should be kept, otherwise as single line FASTA
+pub enum ReadWriter<T: Write> {
+    Fasta(FastaWriter<T>),
+    Fastq(FastqWriter<T>),
+}
+
+impl<T: Write> ReadWriter<T> {
+    pub fn new(writer: T, fastq: bool) -> Self {
+        if fastq {
+            ReadWriter::Fastq(FastqWriter::new(writer))
         } else {
-            Some(Ok(Fasta {
-                name: mem::take(&mut self.curr_name),
-                sequence: seq,
-            }))
+            ReadWriter::Fasta(FastaWriter::new(writer, 0))
+        }
+    }
+
+    pub fn write(&mut self, fasta: &Fasta) -> Result<()> {
+        match self {
+            ReadWriter::Fasta(writer) => writer.write(fasta),
+            ReadWriter::Fastq(writer) => writer.write(fasta),
+        }
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        match self {
+            ReadWriter::Fasta(writer) => writer.flush(),
+            ReadWriter::Fastq(writer) => writer.flush(),
         }
     }
 }
 
-impl<T: Read> FastaIterator<T> {
-    pub fn new(bufreader: BufReader<T>) -> Self {
-        Self {
-            lines_reader: bufreader.lines(),
-            curr_name: String::new()
+//Opens a FASTA or FASTQ file, which can be gzipped. Uncompressed FASTA is memory-mapped
+pub fn open_records(file_name: &str, fastq: bool, gzip: bool, header_split: HeaderSplit) -> Result<Box<dyn Iterator<Item = Result<Fasta>>>> {
+    let iterator: Box<dyn Iterator<Item = Result<Fasta>>> = if fastq {
+        Box::new(FastqIterator::new(open_reader(file_name, gzip)?).with_header_split(header_split))
+    } else if gzip {
+        Box::new(FastaIterator::new(open_reader(file_name, gzip)?))
+    } else {
+        Box::new(MappedFastaIterator::open(file_name)?)
+    };
+
+    Ok(Box::new(iterator.map(move |fasta| {
+        let mut fasta = fasta?;
+        fasta.set_header_split(header_split);
+        Ok(fasta)
+    })))
+}
+
+//Reads the whole FASTQ input, collecting every problem found instead of stopping at the first.
+//Errors reading the file are returned, since nothing after them can be checked
+pub fn validate(file_name: &str, gzip: bool, header_split: HeaderSplit) -> Result<ValidationReport> {
+    let mut report = ValidationReport::default();
+    for record in FastqIterator::new(open_reader(file_name, gzip)?).with_header_split(header_split) {
+        match record {
+            Result::Ok(_) => report.reads += 1,
+            Err(err) => report.problems.push(err.downcast::<FastqProblem>()?),
         }
     }
+    Ok(report)
 }
 
-pub fn parse(args: &PalinArgs) -> Result<Box<dyn Iterator<Item = Result<Fasta>>>>{
-    let reader = get_reader(args)?;
-    if args.mode.is_fq() {
-        Ok(Box::new(FastqIterator::new(reader)))
+pub(crate) fn open_reader(file_name: &str, gzip: bool) -> Result<BufReader<Box<dyn Read>>> {
+    let file = File::open(file_name)?;
+    if gzip {
+        Ok(BufReader::with_capacity(BUFF_SIZE, Box::new(MultiGzDecoder::new(file))))
     } else {
-        Ok(Box::new(FastaIterator::new(reader)))
+        Ok(BufReader::with_capacity(BUFF_SIZE, Box::new(file)))
     }
 }
 
-pub fn get_reader(args: &PalinArgs) -> Result<BufReader<Box<dyn Read>>> {
-    let cmd = &args.mode;
-    let file = File::open(cmd.input_file())?;
+pub fn get_writer(file_name: &str, gzip: bool) -> Result<BufWriter<Box<dyn Write>>> {
+    let file = File::create(file_name)?;
 
-    if cmd.is_fgz() || cmd.is_fqgz(){
-        Ok(BufReader::with_capacity(
+    if gzip {
+        Ok(BufWriter::with_capacity(
             BUFF_SIZE,
-            Box::new(MultiGzDecoder::new(file)),
+            Box::new(GzEncoder::new(file, Compression::default())),
         ))
-    } else if cmd.is_fa() || cmd.is_fq(){
-        return Ok(BufReader::with_capacity(BUFF_SIZE, Box::new(file)));
     } else {
-        bail!("Invalid file format input")
+        Ok(BufWriter::with_capacity(BUFF_SIZE, Box::new(file)))
     }
-}
\ No newline at end of file
+}
+
+//Finds the line width of the first sequence in the input, 0 for FASTQ files
+pub fn get_line_width(file_name: &str, fastq: bool, gzip: bool) -> Result<usize> {
+    if fastq {
+        return Ok(0);
+    }
+    let mut reader = open_reader(file_name, gzip)?;
+    let mut line = Vec::new();
+    while read_line(&mut reader, &mut line)? {
+        if !line.starts_with(b">") {
+            return Ok(line.len());
+        }
+    }
+    Ok(0)
+}
+
+//Reverse complement keeping the case of each base. IUPAC ambiguity codes are complemented to the code for the
+//complementary bases, such as R (A or G) to Y (C or T)
+pub(crate) fn reverse_complement(seq: &[u8]) -> Result<Vec<u8>> {
+    seq.iter()
+        .rev()
+        .map(|&b| {
+            let complement = match b.to_ascii_uppercase() {
+                b'A' => b'T',
+                b'T' => b'A',
+                b'C' => b'G',
+                b'G' => b'C',
+                b'N' => b'N',
+                b'R' => b'Y',
+                b'Y' => b'R',
+                b'S' => b'S',
+                b'W' => b'W',
+                b'K' => b'M',
+                b'M' => b'K',
+                b'B' => b'V',
+                b'V' => b'B',
+                b'D' => b'H',
+                b'H' => b'D',
+                _ => bail!("Not a base pair - check format"),
+            };
+            Ok(if b.is_ascii_lowercase() { complement.to_ascii_lowercase() } else { complement })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{search, Config, DirectConfig, FixedConfig, MirrorConfig, Palindrome, WfaConfig};
+
+    fn parse_fastq(data: &[u8], header_split: HeaderSplit) -> Vec<Result<Fasta>> {
+        FastqIterator::new(BufReader::new(data)).with_header_split(header_split).collect()
+    }
+
+    fn bases(fasta: &Fasta) -> Vec<u8> {
+        fasta.get_sequence().to_contiguous().into_owned()
+    }
+
+    const VALID: &str = "@r1 first read\nACGT\nAC\n+\nIIII\nII\n\n@r2\tsecond\nGGCC\n+r2\n@+!~\n@r3 third\nTTA\n+r3 third\n!!!\n";
+
+    fn check_valid(records: Vec<Result<Fasta>>) {
+        let records: Vec<Fasta> = records.into_iter().collect::<Result<_>>().unwrap();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].get_id(), "r1");
+        assert_eq!(records[0].get_description(), Some("first read"));
+        assert_eq!(bases(&records[0]), b"ACGTAC");
+        assert_eq!(records[0].get_quality(), Some(b"IIIIII".as_slice()));
+        //Quality lines can start with '@' or '+'
+        assert_eq!(records[1].get_id(), "r2");
+        assert_eq!(records[1].get_quality(), Some(b"@+!~".as_slice()));
+        assert_eq!(bases(&records[2]), b"TTA");
+    }
+
+    #[test]
+    fn masks_low_quality_bases() {
+        let mut fasta = Fasta::new("read".to_owned(), b"ACGTA".to_vec().into(), Some(b"II#+I".to_vec()));
+        fasta.mask_low_quality(10);
+        //Quality 10 is kept, only the base below it is masked
+        assert_eq!(bases(&fasta), b"ACNTA");
+        assert_eq!(fasta.get_quality(), Some(b"II#+I".as_slice()));
+
+        let mut fasta = Fasta::new("seq".to_owned(), b"ACGTA".to_vec().into(), None);
+        fasta.mask_low_quality(10);
+        assert_eq!(bases(&fasta), b"ACGTA");
+    }
+
+    #[test]
+    fn fasta_records_keep_raw_bytes() {
+        let data = b">s1 first record\nACgt\nNNac\n>s2\r\nGG\xffA\r\n";
+        let records: Vec<Fasta> = FastaIterator::new(BufReader::new(data.as_slice())).collect::<Result<_>>().unwrap();
+
+        //Bases are read as they are, without uppercasing or checking they are text
+        assert_eq!(records.len(), 2);
+        assert_eq!((records[0].get_id(), records[0].get_description()), ("s1", Some("first record")));
+        assert_eq!(bases(&records[0]), b"ACgtNNac");
+        assert_eq!(bases(&records[1]), b"GG\xffA");
+        assert!(matches!(records[0].get_sequence().slice(1..5), Cow::Borrowed(b"CgtN")));
+    }
+
+    #[test]
+    fn trims_and_reverses_qualities_with_bases() {
+        let mut fasta = Fasta::new("read".to_owned(), b"AACGTT".to_vec().into(), Some(b"!#%')+".to_vec()));
+        fasta.trim(1..5);
+        assert_eq!(bases(&fasta), b"ACGT");
+        assert_eq!(fasta.get_quality(), Some(b"#%')".as_slice()));
+        fasta.reverse_complement().unwrap();
+        assert_eq!(bases(&fasta), b"ACGT");
+        assert_eq!(fasta.get_quality(), Some(b")'%#".as_slice()));
+    }
+
+    #[test]
+    fn set_name_keeps_header_split() {
+        let mut fasta = Fasta::new("read desc".to_owned(), b"ACGT".to_vec().into(), None);
+        fasta.set_header_split(HeaderSplit::Tab);
+        fasta.set_name("r".to_owned());
+        assert_eq!(fasta.get_id(), "r");
+        assert_eq!(fasta.get_description(), None);
+        fasta.set_name("read 1\tlong description".to_owned());
+        assert_eq!(fasta.get_id(), "read 1");
+        assert_eq!(fasta.get_description(), Some("long description"));
+    }
+
+    #[test]
+    fn sequence_changes_keep_quality_length() {
+        let mut fasta = Fasta::new("read".to_owned(), b"ACGT".to_vec().into(), Some(b"IIII".to_vec()));
+        fasta.bases_mut()[1..3].fill(b'N');
+        assert_eq!(bases(&fasta), b"ANNT");
+        assert!(fasta.set_sequence(b"ACG".to_vec().into(), Some(b"IIII".to_vec())).is_err());
+        assert_eq!(bases(&fasta), b"ANNT");
+        fasta.set_sequence(b"ACG".to_vec().into(), Some(b"II#".to_vec())).unwrap();
+        assert_eq!(bases(&fasta), b"ACG");
+        assert_eq!(fasta.get_quality(), Some(b"II#".as_slice()));
+    }
+
+    #[test]
+    fn complements_iupac_codes() {
+        assert_eq!(reverse_complement(b"ACGTNacgtn").unwrap(), b"nacgtNACGT");
+        assert_eq!(reverse_complement(b"RYSWKMBDHV").unwrap(), b"BDHVKMWSRY");
+        assert_eq!(reverse_complement(b"ry").unwrap(), b"ry");
+        assert!(reverse_complement(b"ACGU").is_err());
+    }
+
+    #[test]
+    fn parses_valid_fastq() {
+        check_valid(parse_fastq(VALID.as_bytes(), HeaderSplit::Whitespace));
+    }
+
+    #[test]
+    fn parses_crlf_fastq() {
+        check_valid(parse_fastq(VALID.replace('\n', "\r\n").as_bytes(), HeaderSplit::Whitespace));
+    }
+
+    #[test]
+    fn plus_line_uses_header_split() {
+        let data = b"@r1 a\tdesc\nACGT\n+r1 a\nIIII\n";
+        assert!(parse_fastq(data, HeaderSplit::Tab)[0].is_ok());
+        assert!(parse_fastq(data, HeaderSplit::Whitespace)[0].is_err());
+
+        let data = b"@r1\tdesc\nACGT\n+r1\nIIII\n";
+        assert!(parse_fastq(data, HeaderSplit::Tab)[0].is_ok());
+        assert!(parse_fastq(data, HeaderSplit::Full)[0].is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_records() {
+        let records = parse_fastq(b"@r1\nACGT\n+\nII", HeaderSplit::Whitespace);
+        assert_eq!(records.len(), 1);
+        assert!(records[0].as_ref().unwrap_err().to_string().contains("shorter"));
+
+        let records = parse_fastq(b"@r1\nACGT\n", HeaderSplit::Whitespace);
+        assert_eq!(records.len(), 1);
+        assert!(records[0].as_ref().unwrap_err().to_string().contains("'+' line"));
+    }
+
+    #[test]
+    fn rejects_mismatched_lengths_and_recovers() {
+        let records = parse_fastq(b"@r1\nACGT\n+\nIIIII\n@r2\nAC\n+\nII\n", HeaderSplit::Whitespace);
+        assert_eq!(records.len(), 2);
+        assert!(records[0].as_ref().unwrap_err().to_string().contains("longer"));
+        assert_eq!(records[1].as_ref().unwrap().get_id(), "r2");
+    }
+
+    //Writes data to a file in the temp directory, removed when dropped
+    struct TempFile(std::path::PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, data: &[u8]) -> Self {
+            let path = std::env::temp_dir().join(format!("palindromes_{}_{}", std::process::id(), name));
+            std::fs::write(&path, data).unwrap();
+            Self(path)
+        }
+
+        fn path(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn validate_reports_every_problem() {
+        let file = TempFile::new("problems.fq", b"@r1\nACGT\n+\nIIIII\nACGT\n@r2 ok\nAC\n+\nII\n@r3\nA1\n+\nII\n");
+        let report = validate(file.path(), false, HeaderSplit::Whitespace).unwrap();
+
+        assert_eq!(report.reads, 1);
+        assert_eq!(report.problems.len(), 2);
+        assert_eq!(report.problems[0].line, 4);
+        assert_eq!(report.problems[0].read.as_deref(), Some("r1"));
+        assert!(report.problems[0].message.contains("longer"));
+        assert_eq!(report.problems[1].line, 11);
+        assert_eq!(report.problems[1].read.as_deref(), Some("r3"));
+        assert_eq!(report.problems[1].to_string(), "Line 11: invalid base '1' in r3");
+    }
+
+    fn read_mapped(name: &str, data: &[u8]) -> Vec<Fasta> {
+        let file = TempFile::new(name, data);
+        MappedFastaIterator::open(file.path()).unwrap().collect::<Result<_>>().unwrap()
+    }
+
+    #[test]
+    fn mapped_reader_matches_buffered_reader() {
+        let data = b">s1 desc\nACGTA\nCGTAC\nGT\n>empty\n>s2\nAC\nACGT\nA\n>s3\r\nGGGG\r\nCC\r\n>s4\nacgtn";
+        let mapped = read_mapped("mixed.fa", data);
+        let buffered: Vec<Fasta> = FastaIterator::new(BufReader::new(data.as_slice())).collect::<Result<_>>().unwrap();
+
+        assert_eq!(mapped.len(), 4);
+        assert_eq!(mapped.len(), buffered.len());
+        for (mapped, buffered) in mapped.iter().zip(&buffered) {
+            assert_eq!(mapped.get_name(), buffered.get_name());
+            assert_eq!(bases(mapped), bases(buffered));
+        }
+        assert_eq!(mapped[0].get_id(), "s1");
+        assert_eq!(bases(&mapped[1]), b"ACACGTA");
+        assert_eq!(bases(&mapped[2]), b"GGGGCC");
+        assert_eq!(bases(&mapped[3]), b"acgtn");
+    }
+
+    #[test]
+    fn mapped_reader_reads_fixed_width_in_place() {
+        let records = read_mapped("fixed.fa", b">s1\nACGT\nTGCA\nAA\n>s2\nAC\r\nGT\nA\n");
+        assert!(matches!(records[0].get_sequence(), Sequence::Mapped(_)));
+        assert_eq!(records[0].get_sequence().slice(2..9).as_ref(), b"GTTGCAA");
+        //Mixed line endings can't be read in place
+        assert!(matches!(records[1].get_sequence(), Sequence::Owned(_)));
+        assert_eq!(bases(&records[1]), b"ACGTA");
+    }
+
+    #[test]
+    fn mapped_records_search_the_same_as_owned() {
+        let mut state: u32 = 7;
+        let seq: Vec<u8> = (0..3000)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                b"ACGT"[(state >> 16) as usize % 4]
+            })
+            .collect();
+        let mut data = b">chr1\n".to_vec();
+        for line in seq.chunks(60) {
+            data.extend_from_slice(line);
+            data.push(b'\n');
+        }
+        let file = TempFile::new("search.fa", &data);
+        let mapped = open_records(file.path(), false, false, HeaderSplit::Whitespace).unwrap().next().unwrap().unwrap();
+        assert!(matches!(mapped.get_sequence(), Sequence::Mapped(_)));
+        let owned = Fasta::new("chr1".to_owned(), seq.into(), None);
+
+        let wfa = WfaConfig { min_length: 6, mismatch_proportion: 0.3, both_strands: true, ..Default::default() };
+        let configs = [
+            Config::Wfa(wfa.clone()),
+            Config::ExactMatch(FixedConfig { min_length: 5, gap_len: 3, mismatches: 1 }),
+            Config::Mirror(MirrorConfig { wfa: wfa.clone(), purine_bias: None }),
+            Config::Direct(DirectConfig { wfa: WfaConfig { min_length: 6, ..wfa }, ..Default::default() }),
+        ];
+        for config in &configs {
+            let (mut from_map, mut from_owned) = (Vec::new(), Vec::new());
+            search(&mapped, config, &mut from_map).unwrap();
+            search(&owned, config, &mut from_owned).unwrap();
+            assert!(!from_map.is_empty());
+            let rows = |palins: &[Palindrome]| palins.iter().map(|palin| palin.to_string()).collect::<Vec<_>>();
+            assert_eq!(rows(&from_map), rows(&from_owned));
+        }
+    }
+
+    #[test]
+    fn mapped_reader_rejects_missing_header() {
+        let file = TempFile::new("noheader.fa", b"ACGT\n>s1\nACGT\n");
+        let mut records = MappedFastaIterator::open(file.path()).unwrap();
+        assert!(records.next().unwrap().is_err());
+    }
+}
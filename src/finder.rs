@@ -0,0 +1,109 @@
+use anyhow::{bail, Result};
+
+use crate::{
+    config::Config,
+    direct_repeats::DirectScratch,
+    fasta_parsing::Fasta,
+    long_spacer::LongSpacerScratch,
+    output::PalindromeData,
+    wfa::Waves,
+};
+
+//A palindrome search. The settings are checked once when making the scratch buffers,
+//which are then reused for every record searched on the same thread
+pub trait PalindromeFinder {
+    type Scratch;
+
+    fn scratch(&self) -> Result<Self::Scratch>;
+
+    //Adds the palindromes found in one record to output
+    fn find(&self, fasta: &Fasta, output: &mut Vec<PalindromeData>, scratch: &mut Self::Scratch) -> Result<()>;
+}
+
+//Runs both searches on each record, with their results sorted together by position
+impl<A: PalindromeFinder, B: PalindromeFinder> PalindromeFinder for (A, B) {
+    type Scratch = (A::Scratch, B::Scratch);
+
+    fn scratch(&self) -> Result<Self::Scratch> {
+        Ok((self.0.scratch()?, self.1.scratch()?))
+    }
+
+    fn find(&self, fasta: &Fasta, output: &mut Vec<PalindromeData>, scratch: &mut Self::Scratch) -> Result<()> {
+        let first = output.len();
+        self.0.find(fasta, output, &mut scratch.0)?;
+        self.1.find(fasta, output, &mut scratch.1)?;
+        output[first..].sort_by_key(|palin| (palin.get_start(), palin.get_end()));
+        Ok(())
+    }
+}
+
+//Buffers for whichever search a Config holds
+pub struct ConfigScratch(Scratch);
+
+enum Scratch {
+    Waves(Waves),
+    Fixed(Vec<u8>),
+    Direct(DirectScratch),
+    LongSpacer(LongSpacerScratch),
+}
+
+impl PalindromeFinder for Config {
+    type Scratch = ConfigScratch;
+
+    fn scratch(&self) -> Result<Self::Scratch> {
+        Ok(ConfigScratch(match self {
+            Config::Wfa(config) => Scratch::Waves(config.scratch()?),
+            Config::ExactMatch(config) => Scratch::Fixed(config.scratch()?),
+            Config::Mirror(config) => Scratch::Waves(config.scratch()?),
+            Config::Direct(config) => Scratch::Direct(config.scratch()?),
+            Config::LongSpacer(config) => Scratch::LongSpacer(config.scratch()?),
+        }))
+    }
+
+    fn find(&self, fasta: &Fasta, output: &mut Vec<PalindromeData>, scratch: &mut Self::Scratch) -> Result<()> {
+        match (self, &mut scratch.0) {
+            (Config::Wfa(config), Scratch::Waves(waves)) => config.find(fasta, output, waves),
+            (Config::ExactMatch(config), Scratch::Fixed(codes)) => config.find(fasta, output, codes),
+            (Config::Mirror(config), Scratch::Waves(waves)) => config.find(fasta, output, waves),
+            (Config::Direct(config), Scratch::Direct(direct)) => config.find(fasta, output, direct),
+            (Config::LongSpacer(config), Scratch::LongSpacer(long_spacer)) => config.find(fasta, output, long_spacer),
+            _ => bail!("Scratch buffers were made for a different search"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::{FixedConfig, MirrorConfig, WfaConfig}, output::RepeatType, search};
+
+    //Arms of ACGGTCATTG and CAATGACCGT around a GA spacer, followed by a mirror repeat with arms of TCAGGACATG
+    const SEQ: &[u8] = b"GGGGGACGGTCATTGGACAATGACCGTGGGGGTCAGGACATGGTACAGGACTGGGG";
+
+    fn record() -> Fasta {
+        Fasta::new("seq".to_owned(), SEQ.to_vec().into(), None)
+    }
+
+    #[test]
+    fn pairs_merge_results_by_position() {
+        let mirror = MirrorConfig { wfa: WfaConfig { min_length: 8, ..Default::default() }, purine_bias: None };
+        let inverted = FixedConfig { min_length: 8, gap_len: 3, mismatches: 0 };
+        let mut palins = Vec::new();
+        search(&record(), &(mirror, inverted), &mut palins).unwrap();
+
+        let found: Vec<_> = palins.iter().map(|palin| (palin.get_start(), palin.get_repeat_type())).collect();
+        assert!(found.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+        assert!(found.contains(&(5, RepeatType::Inverted)));
+        assert!(found.iter().any(|&(start, repeat_type)| start > 20 && repeat_type == RepeatType::Mirror));
+    }
+
+    #[test]
+    fn config_scratch_must_match_the_config() {
+        let wfa = Config::Wfa(WfaConfig::default());
+        let fixed = Config::ExactMatch(FixedConfig::default());
+        let mut scratch = wfa.scratch().unwrap();
+        let mut palins = Vec::new();
+        assert!(wfa.find(&record(), &mut palins, &mut scratch).is_ok());
+        assert!(fixed.find(&record(), &mut palins, &mut scratch).is_err());
+    }
+}
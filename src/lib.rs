@@ -0,0 +1,37 @@
+pub mod adapters;
+pub mod config;
+pub mod demux;
+mod direct_repeats;
+mod exact_matches;
+pub mod fasta_parsing;
+pub mod finder;
+mod long_spacer;
+pub mod output;
+mod packed;
+pub mod tails;
+mod wfa;
+
+use anyhow::Result;
+
+pub use config::{
+    AdapterConfig, ClassifyConfig, Config, DemuxConfig, DirectConfig, ExtractConfig, FixedConfig, HeaderSplit,
+    LongSpacerConfig, MirrorConfig, Preset, ScoringConfig, TailConfig, TrimConfig, WfaConfig,
+};
+pub use fasta_parsing::Fasta;
+pub use finder::PalindromeFinder;
+pub use output::{PalindromeData as Palindrome, RepeatType, Strand};
+
+//Finds palindromes in a sequence of bases, which can be upper or lower case with N for unknown bases.
+//Positions are 0-based and the end is inclusive, the same as the output file
+pub fn find_palindromes<F: PalindromeFinder>(seq: &[u8], finder: &F) -> Result<impl Iterator<Item = Palindrome>> {
+    let fasta = Fasta::new(String::new(), seq.to_vec().into(), None);
+    let mut palins = Vec::new();
+    search(&fasta, finder, &mut palins)?;
+    Ok(palins.into_iter())
+}
+
+//Runs the search on one record, adding the palindromes found to output.
+//When searching many records, make the scratch buffers once and call find instead
+pub fn search<F: PalindromeFinder>(fasta: &Fasta, finder: &F, output: &mut Vec<Palindrome>) -> Result<()> {
+    finder.find(fasta, output, &mut finder.scratch()?)
+}
@@ -0,0 +1,239 @@
+use std::{
+    cmp::max,
+    collections::{hash_map::Entry, HashMap, VecDeque},
+};
+
+use crate::{
+    config::LongSpacerConfig,
+    fasta_parsing::Fasta,
+    finder::PalindromeFinder,
+    output::PalindromeData,
+    packed::PackedSeq,
+    wfa::{align_arms, Waves},
+};
+
+use anyhow::{ensure, Result};
+
+//Arms of a palindrome that has been reported, as [start, end) ranges
+struct FoundArms {
+    left: (usize, usize),
+    right: (usize, usize),
+}
+
+impl FoundArms {
+    fn contains_seed(&self, left: usize, right: usize, k: usize) -> bool {
+        left >= self.left.0 && left + k <= self.left.1 && right >= self.right.0 && right + k <= self.right.1
+    }
+}
+
+//K-mer index and reported arms, cleared for each record
+pub struct LongSpacerScratch {
+    kmers: HashMap<u64, VecDeque<usize>>,
+    found: Vec<FoundArms>,
+    waves: Waves,
+}
+
+//Finds palindromes with long gaps by pairing k-mers with their reverse complement and extending them with WFA
+impl PalindromeFinder for LongSpacerConfig {
+    type Scratch = LongSpacerScratch;
+
+    fn scratch(&self) -> Result<LongSpacerScratch> {
+        ensure!(!self.wfa.both_strands, "Both strands are not supported when searching for long gaps");
+        ensure!((1..=32).contains(&self.kmer), "K-mer length must be between 1 and 32");
+        ensure!(self.min_spacer <= self.max_spacer, "Minimum spacer can't be longer than the maximum spacer");
+        Ok(LongSpacerScratch { kmers: HashMap::new(), found: Vec::new(), waves: self.wfa.scratch()? })
+    }
+
+    fn find(&self, fasta: &Fasta, output: &mut Vec<PalindromeData>, scratch: &mut LongSpacerScratch) -> Result<()> {
+        long_spacer_palins(fasta, output, self, scratch)
+    }
+}
+
+fn long_spacer_palins(
+    fasta: &Fasta,
+    output: &mut Vec<PalindromeData>,
+    args: &LongSpacerConfig,
+    scratch: &mut LongSpacerScratch,
+) -> Result<()> {
+    let wfa_args = &args.wfa;
+    let seq = fasta.get_sequence();
+
+    let packed_seq = PackedSeq::new(seq)?;
+
+    let k = args.kmer;
+    let len = seq.len();
+    let first = output.len();
+
+    //K-mers that could be the left seed of a pair, limited to the spacer range behind the current position
+    let LongSpacerScratch { kmers, found, waves } = scratch;
+    kmers.clear();
+    found.clear();
+
+    for right in 0..(len + 1).saturating_sub(k) {
+        if let Some(left) = right.checked_sub(k + args.min_spacer) {
+            if let Some(code) = packed_seq.kmer(left, k) {
+                kmers.entry(code).or_default().push_back(left);
+            }
+        }
+        if let Some(left) = right.checked_sub(k + args.max_spacer + 1) {
+            if let Some(code) = packed_seq.kmer(left, k) {
+                if let Entry::Occupied(mut entry) = kmers.entry(code) {
+                    entry.get_mut().pop_front();
+                    if entry.get().is_empty() {
+                        entry.remove();
+                    }
+                }
+            }
+        }
+
+        let Some(code) = packed_seq.kmer(right, k) else {
+            continue;
+        };
+        let Some(seeds) = kmers.get(&reverse_complement_code(code, k)) else {
+            continue;
+        };
+
+        //Skips k-mers from low complexity or highly repeated sequence
+        if seeds.len() > args.max_seed_hits {
+            continue;
+        }
+
+        found.retain(|arms| arms.right.1 > right);
+
+        for &left in seeds.iter().rev() {
+            if found.iter().any(|arms| arms.contains_seed(left, right, k)) {
+                continue;
+            }
+
+            //Extends the seed inwards over exact matches, since the alignment only extends outwards.
+            //Stops at the minimum spacer, which the outward alignment can only lengthen
+            let mut left_end = left + k;
+            let mut right_start = right;
+            while left_end + args.min_spacer + 1 < right_start && is_complement(&packed_seq, left_end, right_start - 1) {
+                left_end += 1;
+                right_start -= 1;
+            }
+
+            let Some(arms) = align_arms::<true>(&packed_seq, left_end, right_start, waves, wfa_args)? else {
+                continue;
+            };
+            if arms.right_len < wfa_args.min_length {
+                continue;
+            }
+
+            let start = left_end - arms.left_len;
+            let left_arm_end = left_end - arms.gap;
+            let end = right_start + arms.right_len;
+            if found.iter().any(|arms| arms.left.0 == start && arms.right.1 == end) {
+                continue;
+            }
+
+            let spacer = right_start - left_arm_end;
+            let mut sequence = seq.slice(start..left_arm_end).into_owned();
+            sequence.extend_from_slice(format!("[{}]", spacer).as_bytes());
+            sequence.extend_from_slice(&seq.slice(right_start..end));
+            let palin = PalindromeData::new(
+                start as u32,
+                (end - 1) as u32,
+                arms.right_len as u32,
+                spacer as u32,
+                (end - start) as u32,
+                arms.edit_dist,
+                fasta.get_id().to_owned(),
+                sequence,
+            );
+            output.push(palin);
+            found.push(FoundArms {
+                left: (start, left_arm_end),
+                right: (right_start, max(end, right + k)),
+            });
+        }
+    }
+
+    output[first..].sort_by_key(|palin| (palin.get_start(), palin.get_end()));
+    Ok(())
+}
+
+fn is_complement(packed_seq: &PackedSeq, first: usize, second: usize) -> bool {
+    matches!((packed_seq.base(first), packed_seq.base(second)), (Some(a), Some(b)) if a == b ^ 3)
+}
+
+//Since A = 0, C = 1, G = 2, T = 3, complementing flips both bits and reversing swaps the order of the 2-bit groups
+fn reverse_complement_code(code: u64, k: usize) -> u64 {
+    let reversed = (!code).reverse_bits();
+    let reversed = ((reversed >> 1) & 0x5555555555555555) | ((reversed & 0x5555555555555555) << 1);
+    reversed >> (64 - 2 * k)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::WfaConfig, fasta_parsing::reverse_complement};
+
+    #[test]
+    fn spacer_is_at_least_min_spacer() {
+        let arm = b"ACGGTCATTGCAGTCCATGA";
+        let mut seq = b"TTTTT".to_vec();
+        seq.extend_from_slice(arm);
+        seq.extend_from_slice(b"AC");
+        seq.extend(arm.iter().rev().map(|&base| match base {
+            b'A' => b'T',
+            b'C' => b'G',
+            b'G' => b'C',
+            _ => b'A',
+        }));
+        seq.extend_from_slice(b"GGGGG");
+        let fasta = Fasta::new("seq".to_owned(), seq.into(), None);
+
+        let config = LongSpacerConfig { min_spacer: 10, ..Default::default() };
+        let mut scratch = config.scratch().unwrap();
+        let mut palins = Vec::new();
+        config.find(&fasta, &mut palins, &mut scratch).unwrap();
+
+        assert!(!palins.is_empty());
+        assert!(palins.iter().all(|palin| palin.get_gap() >= 10));
+    }
+
+    //Bases from a fixed LCG, so tests are repeatable
+    fn random_seq(len: usize, seed: u32) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                b"ACGT"[(state >> 16) as usize % 4]
+            })
+            .collect()
+    }
+
+    fn find(seq: &[u8], config: &LongSpacerConfig) -> Vec<PalindromeData> {
+        let fasta = Fasta::new("seq".to_owned(), seq.to_vec().into(), None);
+        let mut palins = Vec::new();
+        config.find(&fasta, &mut palins, &mut config.scratch().unwrap()).unwrap();
+        palins
+    }
+
+    #[test]
+    fn finds_arms_across_long_spacers() {
+        let arm = random_seq(40, 21);
+        let right = reverse_complement(&arm).unwrap();
+        let seq = [random_seq(100, 22), arm, random_seq(10000, 23), right, random_seq(100, 24)].concat();
+
+        let config = LongSpacerConfig { wfa: WfaConfig { min_length: 30, ..Default::default() }, min_spacer: 1000, ..Default::default() };
+        let palins = find(&seq, &config);
+        assert_eq!(palins.len(), 1);
+        let palin = &palins[0];
+        assert!(palin.get_start() <= 100 && palin.get_end() >= 10179);
+        assert!(palin.get_arm_length() >= 40);
+        assert_eq!(palin.get_gap(), 10000);
+
+        //Arms further apart than the max spacer aren't paired
+        let config = LongSpacerConfig { max_spacer: 5000, ..config };
+        assert!(find(&seq, &config).is_empty());
+    }
+
+    #[test]
+    fn rejects_min_spacer_over_max() {
+        let config = LongSpacerConfig { min_spacer: 100, max_spacer: 10, ..Default::default() };
+        assert!(config.scratch().is_err());
+    }
+}
@@ -1,10 +1,5 @@
-pub mod command_line;
-pub mod exact_matches;
-pub mod fasta_parsing;
-pub mod output;
-pub mod run_algorithm;
-pub mod wfa;
-mod adapters;
+mod command_line;
+mod run_algorithm;
 
 use anyhow::{Ok, Result};
 use clap::Parser;
@@ -6,11 +6,45 @@ use std::{
 
 use anyhow::Result;
 
-use crate::adapters::Adapter;
+use crate::{adapters::{Adapter, ReadClass, ReadSummary}, config::ExtractConfig, fasta_parsing::{reverse_complement, Fasta, FastaWriter, Sequence, PHRED_OFFSET}, tails::Tail};
 
 pub const BUFF_SIZE: usize = 1 << 20;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Strand {
+    Forward,
+    Reverse,
+    Both,
+}
+
+impl fmt::Display for Strand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Strand::Forward => write!(f, "+"),
+            Strand::Reverse => write!(f, "-"),
+            Strand::Both => write!(f, "."),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RepeatType {
+    Inverted,
+    Mirror,
+    Direct,
+}
+
+impl fmt::Display for RepeatType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RepeatType::Inverted => write!(f, "inverted"),
+            RepeatType::Mirror => write!(f, "mirror"),
+            RepeatType::Direct => write!(f, "direct"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct PalindromeData {
     start: u32,
     end: u32,
@@ -18,12 +52,18 @@ pub struct PalindromeData {
     gap: u32,
     overall_length: u32,
     mismatches: u32,
+    strand: Strand,
+    repeat_type: RepeatType,
     fasta: String,
-    sequence: String,
+    sequence: Vec<u8>,
+    //Mean Phred quality of both arms, for reads with qualities
+    arm_quality: Option<f32>,
+    //Rest of the header, only set when descriptions are kept
+    description: Option<String>,
 }
 impl PalindromeData {
     #[allow(clippy::too_many_arguments)]
-    pub fn new(
+    pub(crate) fn new(
         start: u32,
         end: u32,
         arm_length: u32,
@@ -31,7 +71,7 @@ impl PalindromeData {
         overall_length: u32,
         mismatches: u32,
         fasta: String,
-        sequence: String,
+        sequence: Vec<u8>,
     ) -> Self {
         Self {
             start,
@@ -40,8 +80,87 @@ impl PalindromeData {
             gap,
             overall_length,
             mismatches,
+            strand: Strand::Forward,
+            repeat_type: RepeatType::Inverted,
             fasta,
             sequence,
+            arm_quality: None,
+            description: None,
+        }
+    }
+    pub fn get_start(&self) -> u32 {
+        self.start
+    }
+    pub fn get_end(&self) -> u32 {
+        self.end
+    }
+    pub fn get_arm_length(&self) -> u32 {
+        self.arm_length
+    }
+    pub fn get_gap(&self) -> u32 {
+        self.gap
+    }
+    pub fn get_length(&self) -> u32 {
+        self.overall_length
+    }
+    pub fn get_mismatches(&self) -> u32 {
+        self.mismatches
+    }
+    pub fn get_seq_name(&self) -> &str {
+        &self.fasta
+    }
+    pub fn get_sequence(&self) -> &[u8] {
+        &self.sequence
+    }
+    pub fn get_strand(&self) -> Strand {
+        self.strand
+    }
+    pub fn set_strand(&mut self, strand: Strand) {
+        self.strand = strand;
+    }
+    pub fn get_repeat_type(&self) -> RepeatType {
+        self.repeat_type
+    }
+    pub fn set_repeat_type(&mut self, repeat_type: RepeatType) {
+        self.repeat_type = repeat_type;
+    }
+    pub fn get_arm_quality(&self) -> Option<f32> {
+        self.arm_quality
+    }
+    pub fn get_description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+    //Headers without a description get a "." so the column is never empty
+    pub fn set_description(&mut self, description: Option<&str>) {
+        self.description = Some(description.unwrap_or(".").to_owned());
+    }
+
+    //Sets the mean quality of the arms from the qualities of the whole sequence
+    pub fn set_arm_quality(&mut self, quality: &[u8]) {
+        let (start, end) = (self.start as usize, self.end as usize + 1);
+        let left_len = (self.overall_length - self.arm_length - self.gap) as usize;
+        let left = quality.get(start..start + left_len).unwrap_or_default();
+        let right = quality.get(end - self.arm_length as usize..end).unwrap_or_default();
+
+        let count = left.len() + right.len();
+        if count > 0 {
+            let total: u32 = left.iter().chain(right).map(|&score| score.saturating_sub(PHRED_OFFSET) as u32).sum();
+            self.arm_quality = Some(total as f32 / count as f32);
+        }
+    }
+
+    //Converts a palindrome found in the reverse complement of seq to forward strand coordinates
+    pub(crate) fn into_forward_strand(self, seq: &Sequence) -> Self {
+        let len = seq.len() as u32;
+        let start = len - 1 - self.end;
+        let end = len - 1 - self.start;
+        Self {
+            start,
+            end,
+            arm_length: self.overall_length - self.arm_length - self.gap,
+            sequence: seq.slice(start as usize..end as usize + 1).into_owned(),
+            strand: Strand::Reverse,
+            ..self
         }
     }
 }
@@ -49,27 +168,40 @@ impl fmt::Display for PalindromeData {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
             self.start,
             self.end,
             self.arm_length,
             self.gap,
             self.overall_length,
             self.mismatches,
-            self.fasta.split_once(' ').unwrap().0,
-            self.sequence,
-        )
+            self.strand,
+            self.repeat_type,
+            self.fasta,
+            String::from_utf8_lossy(&self.sequence),
+        )?;
+        if let Some(arm_quality) = self.arm_quality {
+            write!(f, "\t{:.1}", arm_quality)?;
+        }
+        if let Some(description) = &self.description {
+            write!(f, "\t{}", description)?;
+        }
+        Ok(())
     }
 }
 
 
-pub fn write_palins(palins: &mut Vec<PalindromeData>, file_name: &str) -> Result<()> {
+//Adds an Arm-Quality column when the input had qualities, and a Description column when descriptions are kept
+pub fn write_palins(palins: &mut Vec<PalindromeData>, file_name: &str, with_quality: bool, with_description: bool) -> Result<()> {
     let output = File::create(file_name)?;
     let mut writer = BufWriter::with_capacity(BUFF_SIZE, output);
 
+    let quality_column = if with_quality { "\tArm-Quality" } else { "" };
+    let description_column = if with_description { "\tDescription" } else { "" };
     let _ = writeln!(
         writer,
-        "Start\tEnd\tArm-Length\tGap\tLength\tMismatches\tSeq-name\tSequence\n"
+        "Start\tEnd\tArm-Length\tGap\tLength\tMismatches\tStrand\tType\tSeq-name\tSequence{}{}\n",
+        quality_column, description_column
     );
     for palin in palins {
         let _ = writeln!(writer, "{}", palin);
@@ -84,19 +216,192 @@ pub fn write_adapters(adapters: &mut Vec<Adapter>, file_name: &str) -> Result<()
     let mut writer = BufWriter::with_capacity(BUFF_SIZE, output);
     let _ = writeln!(
         writer,
-        "Ref name\tQuery name\tCigar\tScore\tQuery index\tRef index\n"
+        "Ref name\tQuery name\tCigar\tScore\tQuery index\tRef index\tOrientation\n"
     );
     for adapter in adapters{
         let result = adapter.get_result();
-        let _ = writeln!(writer, "{}\t{}\t{}\t{}\t{}\t{}", 
+        let _ = writeln!(writer, "{}\t{}\t{}\t{}\t{}\t{}\t{}", 
         adapter.get_name(), 
         adapter.get_ref(),  
         adapter.get_seq(),
         result.score,
         result.query_idx,
         result.reference_idx,
+        adapter.get_orientation(),
     );
     }
     writer.flush()?;
     Ok(())
-}
\ No newline at end of file
+}
+
+pub fn write_read_summary<W: Write>(writer: &mut W, summary: &ReadSummary) -> Result<()> {
+    let adapters = if summary.adapters.is_empty() {
+        ".".to_owned()
+    } else {
+        summary.adapters.join(",")
+    };
+    writeln!(writer, "{}\t{}\t{}\t{}", summary.read, summary.length, summary.class, adapters)?;
+    Ok(())
+}
+
+//Writes the reads in each class followed by the reads each adapter was found in
+pub fn write_class_counts(class_counts: &[usize], names: &[String], adapter_counts: &[usize], file_name: &str) -> Result<()> {
+    let output = File::create(file_name)?;
+    let mut writer = BufWriter::with_capacity(BUFF_SIZE, output);
+    writeln!(writer, "Group\tName\tReads")?;
+    for (class, count) in ReadClass::ALL.iter().zip(class_counts) {
+        writeln!(writer, "class\t{}\t{}", class, count)?;
+    }
+    for (name, count) in names.iter().zip(adapter_counts) {
+        writeln!(writer, "adapter\t{}\t{}", name, count)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn write_tails(tails: &[Tail], file_name: &str) -> Result<()> {
+    let output = File::create(file_name)?;
+    let mut writer = BufWriter::with_capacity(BUFF_SIZE, output);
+    writeln!(writer, "Read\tTail\tStart\tEnd\tLength")?;
+    for tail in tails {
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}\t{}",
+            tail.get_read(),
+            tail.get_tail_type(),
+            tail.get_start(),
+            tail.get_end(),
+            tail.get_length(),
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+//Writes the palindromes found in one sequence as FASTA records
+pub fn write_extracted<W: Write>(writer: &mut FastaWriter<W>, fasta: &Fasta, palins: &[PalindromeData], cmds: &ExtractConfig) -> Result<()> {
+    let seq = fasta.get_sequence();
+    let seq_id = fasta.get_id();
+
+    for palin in palins {
+        let start = palin.start as usize;
+        let end = palin.end as usize + 1;
+        let right_start = end - palin.arm_length as usize;
+        let spacer_start = right_start - palin.gap as usize;
+
+        let header = format!(
+            "source={} start={} end={} arm_length={} gap={} length={} mismatches={} type={}",
+            seq_id, palin.start, palin.end, palin.arm_length, palin.gap, palin.overall_length, palin.mismatches, palin.repeat_type
+        );
+        let record_id = format!("{}_{}_{}", seq_id, palin.start, palin.end);
+
+        if !cmds.no_full {
+            let flank_start = start.saturating_sub(cmds.flank);
+            let flank_end = usize::min(end + cmds.flank, seq.len());
+            writer.write_record(
+                &format!("{} {} part=full flank_left={} flank_right={}", record_id, header, start - flank_start, flank_end - end),
+                &seq.slice(flank_start..flank_end),
+            )?;
+        }
+        if cmds.left_arm {
+            writer.write_record(&format!("{}_left {} part=left_arm", record_id, header), &seq.slice(start..spacer_start))?;
+        }
+        //Only inverted repeats have a complemented right arm
+        if cmds.right_arm && palin.repeat_type == RepeatType::Inverted {
+            let right_arm = reverse_complement(&seq.slice(right_start..end))?;
+            writer.write_record(&format!("{}_right {} part=right_arm_rc", record_id, header), &right_arm)?;
+        } else if cmds.right_arm {
+            writer.write_record(&format!("{}_right {} part=right_arm", record_id, header), &seq.slice(right_start..end))?;
+        }
+        if cmds.spacer && palin.gap > 0 {
+            writer.write_record(&format!("{}_spacer {} part=spacer", record_id, header), &seq.slice(spacer_start..right_start))?;
+        }
+    }
+    Ok(())
+}
+
+//Masks every base covered by a palindrome, either lowercasing it or replacing it with N
+pub fn mask_palindromes(fasta: &mut Fasta, palins: &[PalindromeData], hard: bool) {
+    for palin in palins {
+        let range = palin.start as usize..=palin.end as usize;
+        if hard {
+            fasta.bases_mut()[range].fill(b'N');
+        } else {
+            fasta.bases_mut()[range].make_ascii_lowercase();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Arms of ACGGT and ACCGT around a CA spacer at 4..16, with four bases either side
+    const SEQ: &[u8] = b"TTTTACGGTCAACCGTGGGG";
+
+    fn palindrome() -> PalindromeData {
+        PalindromeData::new(4, 15, 5, 2, 12, 0, "chr1".to_owned(), SEQ[4..16].to_vec())
+    }
+
+    fn extract(palins: &[PalindromeData], cmds: &ExtractConfig) -> String {
+        let fasta = Fasta::new("chr1 test".to_owned(), SEQ.to_vec().into(), None);
+        let mut out = Vec::new();
+        write_extracted(&mut FastaWriter::new(&mut out, 0), &fasta, palins, cmds).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn extracts_arms_and_spacer() {
+        let cmds = ExtractConfig { flank: 2, left_arm: true, right_arm: true, spacer: true, no_full: false };
+        let header = "source=chr1 start=4 end=15 arm_length=5 gap=2 length=12 mismatches=0 type=inverted";
+        let expected = format!(
+            ">chr1_4_15 {header} part=full flank_left=2 flank_right=2\nTTACGGTCAACCGTGG\n\
+             >chr1_4_15_left {header} part=left_arm\nACGGT\n\
+             >chr1_4_15_right {header} part=right_arm_rc\nACGGT\n\
+             >chr1_4_15_spacer {header} part=spacer\nCA\n"
+        );
+        assert_eq!(extract(&[palindrome()], &cmds), expected);
+    }
+
+    #[test]
+    fn extracts_mirror_arms_as_they_are() {
+        let cmds = ExtractConfig { flank: 10, left_arm: false, right_arm: true, spacer: true, no_full: true };
+        let mut palin = PalindromeData::new(4, 13, 5, 0, 10, 0, "chr1".to_owned(), SEQ[4..14].to_vec());
+        palin.set_repeat_type(RepeatType::Mirror);
+        //Without a gap there is no spacer record, and the right arm isn't complemented
+        let expected = ">chr1_4_13_right source=chr1 start=4 end=13 arm_length=5 gap=0 length=10 mismatches=0 type=mirror part=right_arm\nCAACC\n";
+        assert_eq!(extract(&[palin], &cmds), expected);
+    }
+
+    #[test]
+    fn masks_palindromes() {
+        let second = PalindromeData::new(14, 17, 2, 0, 4, 0, "chr1".to_owned(), SEQ[14..18].to_vec());
+        let palins = [palindrome(), second];
+
+        //Overlapping palindromes are masked as one, and bases outside them are left alone
+        let mut fasta = Fasta::new("chr1".to_owned(), SEQ.to_vec().into(), None);
+        mask_palindromes(&mut fasta, &palins, false);
+        assert_eq!(fasta.get_sequence().to_contiguous().as_ref(), b"TTTTacggtcaaccgtggGG");
+
+        let mut fasta = Fasta::new("chr1".to_owned(), SEQ.to_vec().into(), None);
+        mask_palindromes(&mut fasta, &palins, true);
+        assert_eq!(fasta.get_sequence().to_contiguous().as_ref(), b"TTTTNNNNNNNNNNNNNNGG");
+    }
+
+    #[test]
+    fn arm_quality_averages_both_arms() {
+        //Arms of 4 at 2..6 and 8..12, with a gap of 2 scored 0 so it would lower the mean if counted
+        let mut palin = PalindromeData::new(2, 11, 4, 2, 10, 0, "read".to_owned(), b"ACGTAAACGT".to_vec());
+        let quality = b"!!++++!!++55!!";
+        palin.set_arm_quality(quality);
+        //Left arm scores 10, right arm 10, 10, 20, 20
+        assert_eq!(palin.get_arm_quality(), Some(12.5));
+    }
+
+    #[test]
+    fn arm_quality_needs_qualities() {
+        let mut palin = PalindromeData::new(0, 9, 5, 0, 10, 0, "read".to_owned(), b"ACGTAACGTA".to_vec());
+        palin.set_arm_quality(b"");
+        assert_eq!(palin.get_arm_quality(), None);
+    }
+}
@@ -0,0 +1,453 @@
+use std::cmp::{max, min};
+
+use anyhow::{bail, Result};
+
+use crate::fasta_parsing::Sequence;
+
+const EVEN_BITS: u64 = 0x5555555555555555;
+
+//Sequence packed with 2 bits per base, where A = 0, C = 1, G = 2, T = 3 so the complement of a base is base ^ 3.
+//N is stored as A, with its bit set in n_mask so it never matches anything
+pub(crate) struct PackedSeq {
+    bases: Vec<u64>,
+    n_mask: Vec<u64>,
+    has_n: bool,
+    len: usize,
+}
+
+impl PackedSeq {
+    pub(crate) fn new(seq: &Sequence) -> Result<Self> {
+        //One extra word so reads past the end don't need bounds checks
+        let mut bases = vec![0; seq.len() / 32 + 2];
+        let mut n_mask = vec![0; seq.len() / 64 + 2];
+
+        for (i, base) in seq.lines(0..seq.len()).flatten().enumerate() {
+            let bits = match base {
+                b'A' | b'a' => 0,
+                b'C' | b'c' => 1,
+                b'G' | b'g' => 2,
+                b'T' | b't' => 3,
+                b'N' | b'n' => {
+                    n_mask[i / 64] |= 1 << (i % 64);
+                    0
+                }
+                _ => bail!("Not a base pair - check format"),
+            };
+            bases[i / 32] |= bits << (2 * (i % 32));
+        }
+        let has_n = n_mask.iter().any(|&word| word != 0);
+        Ok(Self { bases, n_mask, has_n, len: seq.len() })
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    //Base at pos, or None for N
+    pub(crate) fn base(&self, pos: usize) -> Option<u8> {
+        if self.n_mask[pos / 64] >> (pos % 64) & 1 == 1 {
+            return None;
+        }
+        Some((self.bases[pos / 32] >> (2 * (pos % 32)) & 3) as u8)
+    }
+
+    //32 bases starting at pos, with the first base in the lowest bits.
+    //The high word is shifted in two steps so an offset of 0 doesn't overflow
+    fn word(&self, pos: usize) -> u64 {
+        let (index, offset) = (pos / 32, 2 * (pos % 32));
+        (self.bases[index] >> offset) | ((self.bases[index + 1] << 1) << (63 - offset))
+    }
+
+    //N flags of the 32 bases starting at pos
+    fn n_word(&self, pos: usize) -> u32 {
+        let (index, offset) = (pos / 64, pos % 64);
+        ((self.n_mask[index] >> offset) | ((self.n_mask[index + 1] << 1) << (63 - offset))) as u32
+    }
+
+    //N flags of both sides spread to the bases they belong to, if the sequence has any
+    fn n_bases(&self, first: usize, second: impl FnOnce(&Self) -> u32) -> u64 {
+        if self.has_n {
+            spread_bits(self.n_word(first) | second(self))
+        } else {
+            0
+        }
+    }
+
+    //32 bases ending just before end read backwards, so base end - 1 is in the lowest bits
+    fn word_rev(&self, end: usize) -> u64 {
+        let word = if end >= 32 {
+            self.word(end - 32)
+        } else {
+            self.word(0) << (64 - 2 * end)
+        };
+        reverse_bases(word)
+    }
+
+    fn n_word_rev(&self, end: usize) -> u32 {
+        if end >= 32 {
+            self.n_word(end - 32).reverse_bits()
+        } else {
+            (self.n_word(0) << (32 - end)).reverse_bits()
+        }
+    }
+
+    //2-bit code of the k bases starting at pos with the first base in the lowest bits, or None if any of them is N
+    pub(crate) fn kmer(&self, pos: usize, k: usize) -> Option<u64> {
+        let n_mask = if k == 32 { u32::MAX } else { (1 << k) - 1 };
+        if self.has_n && self.n_word(pos) & n_mask != 0 {
+            return None;
+        }
+        let mask = if k == 32 { u64::MAX } else { (1 << (2 * k)) - 1 };
+        Some(self.word(pos) & mask)
+    }
+
+    //Counts up to 32 matches between the bases from right onwards and the bases before left_end read backwards.
+    //COMPLEMENT matches complementary bases (palindromes), otherwise identical ones (mirror repeats)
+    fn count_matching<const COMPLEMENT: bool>(&self, right: usize, left_end: usize) -> usize {
+        let valid = min(min(self.len - right, left_end), 32);
+        if valid == 0 {
+            return 0;
+        }
+        let left = if COMPLEMENT {
+            !self.word_rev(left_end)
+        } else {
+            self.word_rev(left_end)
+        };
+        let diff = self.word(right) ^ left;
+        let mismatches = (diff | diff >> 1) & EVEN_BITS;
+        let n_bases = self.n_bases(right, |seq| seq.n_word_rev(left_end));
+        min(((mismatches | n_bases).trailing_zeros() / 2) as usize, valid)
+    }
+
+    //Extends outwards from right and left_end while the bases match
+    pub(crate) fn extend<const COMPLEMENT: bool>(&self, mut right: usize, mut left_end: usize) -> usize {
+        let mut counter = 0;
+
+        loop {
+            let count = self.count_matching::<COMPLEMENT>(right, left_end);
+            counter += count;
+            right += count;
+            left_end -= count;
+            if count < 32 {
+                return counter;
+            }
+
+            //Most extensions stop within the first word, so SIMD is only worth it for long matches
+            let skipped = skip_matching::<COMPLEMENT>(self, right, left_end);
+            counter += skipped;
+            right += skipped;
+            left_end -= skipped;
+        }
+    }
+
+    //Whether any of the 128 bases from pos is N
+    #[cfg(target_arch = "x86_64")]
+    fn has_n_in_block(&self, pos: usize) -> bool {
+        self.has_n && (0..4).any(|i| self.n_word(pos + 32 * i) != 0)
+    }
+
+    //Counts up to max_len identical bases from both positions reading forwards, used for direct repeats
+    pub(crate) fn count_equal(&self, mut first: usize, mut second: usize, max_len: usize) -> usize {
+        let max_len = min(max_len, self.len - max(first, second));
+        let mut counter = 0;
+
+        while counter < max_len {
+            let diff = self.word(first) ^ self.word(second);
+            let mismatches = (diff | diff >> 1) & EVEN_BITS;
+            let n_bases = self.n_bases(first, |seq| seq.n_word(second));
+            let count = min((mismatches | n_bases).trailing_zeros() as usize / 2, 32);
+
+            counter += count;
+            first += count;
+            second += count;
+            if count < 32 {
+                break;
+            }
+        }
+        min(counter, max_len)
+    }
+}
+
+//Reverses the order of the 2-bit bases in a word, by reversing the bytes and then the bases within each byte
+fn reverse_bases(word: u64) -> u64 {
+    let word = word.swap_bytes();
+    let word = ((word >> 4) & 0x0F0F0F0F0F0F0F0F) | ((word & 0x0F0F0F0F0F0F0F0F) << 4);
+    ((word >> 2) & 0x3333333333333333) | ((word & 0x3333333333333333) << 2)
+}
+
+//Skips over long exact matches 128 bases at a time with AVX2, leaving the rest to count_matching.
+//Without AVX2 nothing is skipped, and count_matching carries on 32 bases at a time
+#[cfg(target_arch = "x86_64")]
+fn skip_matching<const COMPLEMENT: bool>(seq: &PackedSeq, right: usize, left_end: usize) -> usize {
+    if is_x86_feature_detected!("avx2") {
+        unsafe { skip_matching_avx2::<COMPLEMENT>(seq, right, left_end) }
+    } else {
+        0
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn skip_matching<const COMPLEMENT: bool>(_seq: &PackedSeq, _right: usize, _left_end: usize) -> usize {
+    0
+}
+
+//Compares the 128 bases from right with the 128 bases before left_end read backwards, the same as 4 calls to count_matching.
+//Blocks with an N are left to count_matching
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn skip_matching_avx2<const COMPLEMENT: bool>(seq: &PackedSeq, mut right: usize, mut left_end: usize) -> usize {
+    use std::arch::x86_64::*;
+
+    let byte_reverse = _mm256_setr_epi8(
+        7, 6, 5, 4, 3, 2, 1, 0, 15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0, 15, 14, 13, 12, 11, 10, 9, 8,
+    );
+    let nibbles = _mm256_set1_epi8(0x0F);
+    let pairs = _mm256_set1_epi8(0x33);
+    let flip = if COMPLEMENT { _mm256_set1_epi8(-1) } else { _mm256_setzero_si256() };
+
+    let mut counter = 0;
+    while seq.len - right >= 128 && left_end >= 128 {
+        if seq.has_n_in_block(right) || seq.has_n_in_block(left_end - 128) {
+            break;
+        }
+        let right_vec = load_bases(seq, right);
+
+        //Reverses the order of the words, then the bytes in each word and the bases in each byte, the same as reverse_bases
+        let left_vec = _mm256_permute4x64_epi64(load_bases(seq, left_end - 128), 0x1B);
+        let left_vec = _mm256_shuffle_epi8(left_vec, byte_reverse);
+        let left_vec = _mm256_or_si256(
+            _mm256_and_si256(_mm256_srli_epi16(left_vec, 4), nibbles),
+            _mm256_slli_epi16(_mm256_and_si256(left_vec, nibbles), 4),
+        );
+        let left_vec = _mm256_or_si256(
+            _mm256_and_si256(_mm256_srli_epi16(left_vec, 2), pairs),
+            _mm256_slli_epi16(_mm256_and_si256(left_vec, pairs), 2),
+        );
+
+        let diff = _mm256_xor_si256(_mm256_xor_si256(right_vec, left_vec), flip);
+        if _mm256_testz_si256(diff, diff) == 0 {
+            break;
+        }
+        counter += 128;
+        right += 128;
+        left_end -= 128;
+    }
+    counter
+}
+
+//The 128 bases from pos as 4 words, the same as 4 calls to word. Every word is shifted by the same offset,
+//and a shift of 64 gives 0 so an offset of 0 needs no special case
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn load_bases(seq: &PackedSeq, pos: usize) -> std::arch::x86_64::__m256i {
+    use std::arch::x86_64::*;
+
+    let (index, offset) = (pos / 32, 2 * (pos % 32));
+    let words = &seq.bases[index..index + 5];
+    let low = _mm256_loadu_si256(words.as_ptr() as *const __m256i);
+    let high = _mm256_loadu_si256(words[1..].as_ptr() as *const __m256i);
+    _mm256_or_si256(
+        _mm256_srl_epi64(low, _mm_cvtsi64_si128(offset as i64)),
+        _mm256_sll_epi64(high, _mm_cvtsi64_si128(64 - offset as i64)),
+    )
+}
+
+//Moves each of the 32 bits to the even bit of its 2-bit base
+fn spread_bits(bits: u32) -> u64 {
+    let mut bits = bits as u64;
+    bits = (bits | bits << 16) & 0x0000FFFF0000FFFF;
+    bits = (bits | bits << 8) & 0x00FF00FF00FF00FF;
+    bits = (bits | bits << 4) & 0x0F0F0F0F0F0F0F0F;
+    bits = (bits | bits << 2) & 0x3333333333333333;
+    (bits | bits << 1) & EVEN_BITS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn complement(base: u8) -> u8 {
+        match base {
+            b'A' => b'T',
+            b'C' => b'G',
+            b'G' => b'C',
+            b'T' => b'A',
+            _ => b'N',
+        }
+    }
+
+    //Bases from a fixed LCG, so tests are repeatable
+    fn random_seq(len: usize, seed: u32) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                b"ACGT"[(state >> 16) as usize % 4]
+            })
+            .collect()
+    }
+
+    fn pack(seq: &[u8]) -> PackedSeq {
+        PackedSeq::new(&seq.to_vec().into()).unwrap()
+    }
+
+    fn naive_extend(seq: &[u8], complement_bases: bool, right: usize, left_end: usize) -> usize {
+        (0..)
+            .take_while(|&i| {
+                if right + i >= seq.len() || i >= left_end {
+                    return false;
+                }
+                let (a, b) = (seq[right + i], seq[left_end - 1 - i]);
+                let b = if complement_bases { complement(b) } else { b };
+                a != b'N' && b != b'N' && a == b
+            })
+            .count()
+    }
+
+    fn naive_count_equal(seq: &[u8], first: usize, second: usize, max_len: usize) -> usize {
+        (0..max_len)
+            .take_while(|&i| {
+                first + i < seq.len() && second + i < seq.len() && seq[first + i] != b'N' && seq[first + i] == seq[second + i]
+            })
+            .count()
+    }
+
+    //Sequences with long palindromes, mirror repeats and tandem copies, with and without N
+    fn test_seqs() -> Vec<Vec<u8>> {
+        let arm = random_seq(150, 1);
+        let palindrome = [arm.clone(), arm.iter().rev().map(|&base| complement(base)).collect()].concat();
+        let mirror = [arm.clone(), arm.iter().rev().copied().collect()].concat();
+        let tandem = [arm.clone(), arm.clone(), arm.clone()].concat();
+        let mut with_n = vec![palindrome.clone(), mirror.clone(), tandem.clone()];
+        for seq in &mut with_n {
+            for pos in [3, 40, 64, 97, 128, 200] {
+                seq[pos] = b'N';
+            }
+        }
+        let mut seqs = vec![palindrome, mirror, tandem, vec![b'A'; 300], random_seq(300, 7)];
+        seqs.extend(with_n);
+        seqs
+    }
+
+    #[test]
+    fn extend_matches_naive() {
+        for seq in test_seqs() {
+            let packed = pack(&seq);
+            for right in 0..=seq.len() {
+                for left_end in 0..=right {
+                    assert_eq!(packed.extend::<true>(right, left_end), naive_extend(&seq, true, right, left_end));
+                    assert_eq!(packed.extend::<false>(right, left_end), naive_extend(&seq, false, right, left_end));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn count_equal_matches_naive() {
+        for seq in test_seqs() {
+            let packed = pack(&seq);
+            for first in 0..seq.len() {
+                for second in first..seq.len() {
+                    for max_len in [0, 31, 32, 33, 64, 128, seq.len()] {
+                        assert_eq!(packed.count_equal(first, second, max_len), naive_count_equal(&seq, first, second, max_len));
+                    }
+                }
+            }
+        }
+    }
+
+    //Arms long enough for several 128-base blocks, with N inside and between blocks
+    fn long_seqs() -> Vec<Vec<u8>> {
+        let arm = random_seq(700, 3);
+        let palindrome = [arm.clone(), arm.iter().rev().map(|&base| complement(base)).collect()].concat();
+        let mirror = [arm.clone(), arm.iter().rev().copied().collect()].concat();
+        let mut with_n = vec![palindrome.clone(), mirror.clone()];
+        for seq in &mut with_n {
+            for pos in [60, 450, 700 + 131, 700 + 256, 1350] {
+                seq[pos] = b'N';
+            }
+        }
+        let mut seqs = vec![palindrome, mirror];
+        seqs.extend(with_n);
+        seqs
+    }
+
+    #[test]
+    fn long_extensions_match_naive() {
+        for seq in long_seqs() {
+            let packed = pack(&seq);
+            for right in (0..=seq.len()).step_by(7) {
+                for left_end in (0..=right).step_by(5).chain(right.saturating_sub(40)..=right) {
+                    assert_eq!(packed.extend::<true>(right, left_end), naive_extend(&seq, true, right, left_end));
+                    assert_eq!(packed.extend::<false>(right, left_end), naive_extend(&seq, false, right, left_end));
+                }
+            }
+        }
+    }
+
+    fn has_avx2() -> bool {
+        #[cfg(target_arch = "x86_64")]
+        return is_x86_feature_detected!("avx2");
+        #[cfg(not(target_arch = "x86_64"))]
+        false
+    }
+
+    //Whole blocks of 128 matching bases from count_matching, which the kernel should skip
+    fn scalar_skip(packed: &PackedSeq, mut right: usize, mut left_end: usize) -> usize {
+        let mut skipped = 0;
+        while packed.len() - right >= 128 && left_end >= 128 {
+            if (0..4).any(|i| packed.count_matching::<true>(right + 32 * i, left_end - 32 * i) < 32) {
+                break;
+            }
+            skipped += 128;
+            right += 128;
+            left_end -= 128;
+        }
+        skipped
+    }
+
+    //Padding shifts the palindrome through every offset within a word, so the kernel's shifts are checked from 0 to 62 bits
+    #[test]
+    fn skip_matching_agrees_with_count_matching() {
+        for seq in long_seqs() {
+            for pad in 0..32 {
+                let padded = [random_seq(pad, 5), seq.clone()].concat();
+                let packed = pack(&padded);
+                let centre = pad + 700;
+                for (right, left_end) in [(centre, centre), (centre + 32, centre - 32), (centre + 1, centre)] {
+                    let skipped = skip_matching::<true>(&packed, right, left_end);
+                    if has_avx2() {
+                        assert_eq!(skipped, scalar_skip(&packed, right, left_end));
+                    } else {
+                        assert_eq!(skipped, 0);
+                    }
+                    assert_eq!(packed.extend::<true>(right, left_end), naive_extend(&padded, true, right, left_end));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn skip_matching_uses_avx2_when_available() {
+        if !has_avx2() {
+            return;
+        }
+        let palindrome = &long_seqs()[0];
+        assert_eq!(skip_matching::<true>(&pack(palindrome), 700, 700), 640);
+    }
+
+    //Palindromes with arms just around the word boundaries, broken by a mismatch on each side
+    #[test]
+    fn extend_stops_at_word_boundaries() {
+        for arm_len in [31, 32, 33, 63, 64, 65, 127, 128, 129] {
+            let arm = random_seq(arm_len, arm_len as u32);
+            let seq = [b"CC".as_slice(), &arm, &arm.iter().rev().map(|&base| complement(base)).collect::<Vec<_>>(), b"CC"].concat();
+            let packed = pack(&seq);
+            assert_eq!(packed.extend::<true>(2 + arm_len, 2 + arm_len), arm_len);
+
+            let mut with_n = seq.clone();
+            with_n[2] = b'N';
+            assert_eq!(pack(&with_n).extend::<true>(2 + arm_len, 2 + arm_len), arm_len - 1);
+        }
+    }
+}
@@ -1,44 +1,213 @@
+use std::{fs::File, io::{BufWriter, Write}};
 
-use anyhow::Result;
+use anyhow::{bail, ensure, Result};
 
-use crate::{
-    adapters::align_adapters, command_line::{
-        AdapterArgs, AlgorithmType::{ExactMatch, Wfa, Adapters}, PalinArgs
-    }, exact_matches::fixed_match, fasta_parsing::{parse, Fasta}, output::{write_adapters, write_palins, PalindromeData}, wfa::wfa_palins
+use palindromes::{
+    adapters::{align_adapters, classify_read, split_adapters, trim_adapters, AdapterSet, ReadClass}, demux::demux_reads, config::{AdapterConfig, ClassifyConfig, Config, DirectConfig, ExtractConfig, FixedConfig, LongSpacerConfig, MirrorConfig, TailConfig, TrimConfig, WfaConfig}, finder::PalindromeFinder, fasta_parsing::{self, get_line_width, get_writer, open_records, Fasta, FastaWriter, ReadWriter}, output::{mask_palindromes, write_adapters, write_class_counts, write_extracted, write_palins, write_read_summary, write_tails, BUFF_SIZE}, tails::find_tails
 };
 
+use crate::command_line::{
+    AdapterArgs, ClassifyArgs, AlgorithmType::{ExactMatch, Wfa, Mirror, Direct, LongSpacer, Adapters, Classify, Tails, Trim, Split, Demux, Extract, Mask}, ExtractArgs, MaskArgs, PalinArgs, TailArgs, TrimArgs
+};
+
+fn parse(args: &PalinArgs) -> Result<Box<dyn Iterator<Item = Result<Fasta>>>> {
+    let cmd = &args.mode;
+    if !(cmd.is_fa() || cmd.is_fgz() || cmd.is_fq() || cmd.is_fqgz()) {
+        bail!("Invalid file format input");
+    }
+    open_records(cmd.input_file(), cmd.is_fq() || cmd.is_fqgz(), cmd.is_fgz() || cmd.is_fqgz(), args.header_split.into())
+}
+
+fn validate(args: &PalinArgs) -> Result<()> {
+    ensure!(args.mode.is_fq() || args.mode.is_fqgz(), "Validation needs FASTQ input");
+    let report = fasta_parsing::validate(args.mode.input_file(), args.mode.is_fqgz(), args.header_split.into())?;
+    for problem in &report.problems {
+        println!("{}", problem);
+    }
+    println!("{} reads, {} problems", report.reads, report.problems.len());
+    ensure!(report.problems.is_empty(), "{} has {} problems", args.mode.input_file(), report.problems.len());
+    Ok(())
+}
+
 pub fn run(args: &PalinArgs) -> Result<()> {
-    let iterator = parse(args)?;
+    if args.validate {
+        return validate(args);
+    }
+    let mut iterator = parse(args)?;
+    if let Some(min_quality) = args.min_quality {
+        iterator = mask_low_quality(args, iterator, min_quality)?;
+    }
     let output_file = &args.mode.output_file();
     
     match &args.mode {
-        Wfa(cmds) => run_algorithm(iterator, output_file, |fasta, palins| wfa_palins(fasta, palins, cmds))?,
-        ExactMatch(cmds) => run_algorithm(iterator, output_file, |fasta, palins| fixed_match(fasta, palins, cmds))?,
+        Wfa(cmds) => run_algorithm(iterator, output_file, args.keep_description, &WfaConfig::from(cmds))?,
+        ExactMatch(cmds) => run_algorithm(iterator, output_file, args.keep_description, &FixedConfig::from(cmds))?,
+        Mirror(cmds) => run_algorithm(iterator, output_file, args.keep_description, &MirrorConfig::from(cmds))?,
+        Direct(cmds) => run_algorithm(iterator, output_file, args.keep_description, &DirectConfig::from(cmds))?,
+        LongSpacer(cmds) => run_algorithm(iterator, output_file, args.keep_description, &LongSpacerConfig::from(cmds))?,
         Adapters(cmds) => run_adapters(cmds, iterator, output_file)?,
+        Classify(cmds) => run_classify(cmds, iterator, output_file)?,
+        Tails(cmds) => run_tails(cmds, iterator, output_file)?,
+        Trim(cmds) => run_trim(cmds, iterator, output_file, args.mode.is_fq() || args.mode.is_fqgz(), false)?,
+        Split(cmds) => run_trim(cmds, iterator, output_file, args.mode.is_fq() || args.mode.is_fqgz(), true)?,
+        Demux(cmds) => demux_reads(&cmds.into(), &cmds.output_dir, cmds.gzip, iterator, args.mode.is_fq() || args.mode.is_fqgz())?,
+        Extract(cmds) => run_extract(cmds, iterator, output_file)?,
+        Mask(cmds) => {
+            let line_width = match cmds.line_width {
+                Some(width) => width,
+                None => get_line_width(args.mode.input_file(), args.mode.is_fq() || args.mode.is_fqgz(), args.mode.is_fgz() || args.mode.is_fqgz())?,
+            };
+            run_mask(cmds, iterator, output_file, line_width)?
+        }
     }
     
     Ok(())
 }
 
+//Low quality bases are searched as N, which never matches, so a miscall can't match by chance.
+//Arms can still extend over them, counting each one as a mismatch
+fn mask_low_quality(args: &PalinArgs, iterator: Box<dyn Iterator<Item = Result<Fasta>>>, min_quality: u8) -> Result<Box<dyn Iterator<Item = Result<Fasta>>>> {
+    ensure!(args.mode.is_fq() || args.mode.is_fqgz(), "Quality masking needs FASTQ input");
+    ensure!(
+        matches!(args.mode, Wfa(_) | ExactMatch(_) | Mirror(_) | Direct(_) | LongSpacer(_) | Extract(_) | Mask(_)),
+        "Quality masking only applies to palindrome searches"
+    );
+    Ok(Box::new(iterator.map(move |fasta| {
+        let mut fasta = fasta?;
+        fasta.mask_low_quality(min_quality);
+        Ok(fasta)
+    })))
+}
+
 fn run_adapters(cmds: &AdapterArgs, iterator: Box<dyn Iterator<Item = Result<Fasta>>>, output_file: &str) -> Result<()> {
+    let config = AdapterConfig::from(cmds);
+    let adapter_set = AdapterSet::load(&config)?;
     let mut adapters = Vec::new();
     for fasta in iterator {
-        align_adapters(&mut fasta?, cmds, &mut adapters)?;
+        align_adapters(&mut fasta?, &adapter_set, &config, &mut adapters)?;
     }
     write_adapters(&mut adapters, output_file)?;
     Ok(())
 }
 
-fn run_algorithm<F>(iterator: Box<dyn Iterator<Item = Result<Fasta>>>, output_file: &str, algo: F) -> Result<()>
-where
-    F: Fn(Fasta, &mut Vec<PalindromeData>) -> Result<()>,
-{
+//Writes a row for every read as it is classified, and the counts once all reads are done
+fn run_classify(cmds: &ClassifyArgs, iterator: Box<dyn Iterator<Item = Result<Fasta>>>, output_file: &str) -> Result<()> {
+    let config = ClassifyConfig::from(cmds);
+    let adapter_set = AdapterSet::load(&config.adapters)?;
+    let names = adapter_set.names();
+
+    let mut writer = BufWriter::with_capacity(BUFF_SIZE, File::create(output_file)?);
+    writeln!(writer, "Read\tLength\tClass\tAdapters")?;
+
+    let mut class_counts = [0; ReadClass::ALL.len()];
+    let mut adapter_counts = vec![0; names.len()];
+    let mut hits = Vec::new();
+    for fasta in iterator {
+        let summary = classify_read(&mut fasta?, &adapter_set, &config, &mut hits)?;
+        write_read_summary(&mut writer, &summary)?;
+
+        class_counts[summary.class as usize] += 1;
+        for (name, count) in names.iter().zip(adapter_counts.iter_mut()) {
+            if summary.adapters.contains(name) {
+                *count += 1;
+            }
+        }
+    }
+    writer.flush()?;
+    write_class_counts(&class_counts, names, &adapter_counts, &cmds.summary)
+}
+
+fn run_tails(cmds: &TailArgs, iterator: Box<dyn Iterator<Item = Result<Fasta>>>, output_file: &str) -> Result<()> {
+    let config = TailConfig::from(cmds);
+    let mut tails = Vec::new();
+    for fasta in iterator {
+        find_tails(&fasta?, &config, &mut tails);
+    }
+    write_tails(&tails, output_file)
+}
+
+//Writes FASTQ when the input is FASTQ, so the qualities are kept
+fn run_trim(cmds: &TrimArgs, iterator: Box<dyn Iterator<Item = Result<Fasta>>>, output_file: &str, fastq: bool, split: bool) -> Result<()> {
+    let mut writer = ReadWriter::new(get_writer(output_file, cmds.gzip)?, fastq);
+
+    let config = TrimConfig::from(cmds);
+    let adapter_set = AdapterSet::load(&config.adapters)?;
+    let mut hits = Vec::new();
+    let mut reads = Vec::new();
+    for fasta in iterator {
+        let mut fasta = fasta?;
+        if split {
+            split_adapters(fasta, &adapter_set, &config, &mut hits, &mut reads)?;
+        } else {
+            trim_adapters(&mut fasta, &adapter_set, &config, &mut hits)?;
+            reads.push(fasta);
+        }
+        for read in reads.drain(..) {
+            if read.get_sequence().len() >= cmds.min_length {
+                writer.write(&read)?;
+            }
+        }
+    }
+    writer.flush()
+}
+
+fn run_extract(cmds: &ExtractArgs, iterator: Box<dyn Iterator<Item = Result<Fasta>>>, output_file: &str) -> Result<()> {
+    let mut writer = FastaWriter::new(get_writer(output_file, false)?, 0);
+
+    let extract = ExtractConfig::from(cmds);
+    let config = Config::from(&cmds.search);
+    let mut scratch = config.scratch()?;
+    let mut palins = Vec::new();
+    for fasta in iterator {
+        let fasta = fasta?;
+        config.find(&fasta, &mut palins, &mut scratch)?;
+        write_extracted(&mut writer, &fasta, &palins, &extract)?;
+        palins.clear();
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn run_mask(cmds: &MaskArgs, iterator: Box<dyn Iterator<Item = Result<Fasta>>>, output_file: &str, line_width: usize) -> Result<()> {
+    let mut writer = FastaWriter::new(get_writer(output_file, cmds.gzip)?, line_width);
+
+    let config = Config::from(&cmds.search);
+    let mut scratch = config.scratch()?;
     let mut palins = Vec::new();
     for fasta in iterator {
-        algo(fasta?, &mut palins)?;
+        let mut fasta = fasta?;
+        config.find(&fasta, &mut palins, &mut scratch)?;
+        mask_palindromes(&mut fasta, &palins, cmds.hard);
+        writer.write(&fasta)?;
+        palins.clear();
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn run_algorithm<F: PalindromeFinder>(iterator: Box<dyn Iterator<Item = Result<Fasta>>>, output_file: &str, keep_description: bool, finder: &F) -> Result<()> {
+    let mut scratch = finder.scratch()?;
+    let mut palins = Vec::new();
+    let mut with_quality = false;
+    for fasta in iterator {
+        let fasta = fasta?;
+        let first = palins.len();
+        finder.find(&fasta, &mut palins, &mut scratch)?;
+
+        if let Some(quality) = fasta.get_quality() {
+            with_quality = true;
+            for palin in &mut palins[first..] {
+                palin.set_arm_quality(quality);
+            }
+        }
+        if keep_description {
+            for palin in &mut palins[first..] {
+                palin.set_description(fasta.get_description());
+            }
+        }
     }
 
-    write_palins(&mut palins, output_file)?;
+    write_palins(&mut palins, output_file, with_quality, keep_description)?;
 
     Ok(())
 }
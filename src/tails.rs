@@ -0,0 +1,185 @@
+use std::{cmp::min, fmt::Display, ops::Range};
+
+use crate::{config::TailConfig, fasta_parsing::Fasta};
+
+//Score of any other base within a tail, so a tail can hold one error for every two tail bases around it
+const MISMATCH_PENALTY: i32 = 2;
+
+//Bases allowed between the end of the read and the start of its tail, such as a miscalled last base
+const END_TOLERANCE: usize = 3;
+
+#[derive(Debug, Clone, Copy)]
+pub enum TailType {
+    PolyA,
+    PolyT,
+    PolyG,
+}
+
+impl TailType {
+    fn base(&self) -> u8 {
+        match self {
+            TailType::PolyA => b'A',
+            TailType::PolyT => b'T',
+            TailType::PolyG => b'G',
+        }
+    }
+
+    //Poly-T is found at the 5' end of reads from the reverse strand, the others at the 3' end
+    fn at_start(&self) -> bool {
+        matches!(self, TailType::PolyT)
+    }
+}
+
+impl Display for TailType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TailType::PolyA => write!(f, "poly-A"),
+            TailType::PolyT => write!(f, "poly-T"),
+            TailType::PolyG => write!(f, "poly-G"),
+        }
+    }
+}
+
+pub struct Tail {
+    read: String,
+    tail_type: TailType,
+    //Position of the tail in the read, with the end exclusive
+    start: usize,
+    end: usize,
+}
+
+impl Tail {
+    pub fn get_read(&self) -> &str {
+        &self.read
+    }
+    pub fn get_tail_type(&self) -> TailType {
+        self.tail_type
+    }
+    pub fn get_start(&self) -> usize {
+        self.start
+    }
+    pub fn get_end(&self) -> usize {
+        self.end
+    }
+    pub fn get_length(&self) -> usize {
+        self.end - self.start
+    }
+}
+
+//Finds the tail of each enabled type at its end of the read, searching at most window bases inwards
+pub fn find_tails(fasta: &Fasta, cmds: &TailConfig, output: &mut Vec<Tail>) {
+    let seq = fasta.get_sequence();
+    let len = seq.len();
+    let read = fasta.get_id();
+
+    let tails = [
+        (TailType::PolyA, cmds.poly_a),
+        (TailType::PolyT, cmds.poly_t),
+        (TailType::PolyG, cmds.poly_g),
+    ];
+    for (tail_type, min_length) in tails {
+        let Some(min_length) = min_length else {
+            continue;
+        };
+        //The window is read from the end of the read inwards, so the tail always starts near index 0
+        let (start, end) = if tail_type.at_start() {
+            let window = seq.slice(0..min(cmds.window, len));
+            let Some(run) = anchored_run(window.iter().copied(), tail_type.base()) else {
+                continue;
+            };
+            (run.start, run.end)
+        } else {
+            let window = seq.slice(len.saturating_sub(cmds.window)..len);
+            let Some(run) = anchored_run(window.iter().rev().copied(), tail_type.base()) else {
+                continue;
+            };
+            (len - run.end, len - run.start)
+        };
+
+        if end - start >= min_length {
+            output.push(Tail {
+                read: read.to_owned(),
+                tail_type,
+                start,
+                end,
+            });
+        }
+    }
+}
+
+//Highest scoring run of base starting within END_TOLERANCE bases of the start of seq, where the base scores 1
+//and anything else MISMATCH_PENALTY. The run extends until its score drops to 0 and ends on the base,
+//so errors only count inside the tail and runs away from the end of the read are never reported
+fn anchored_run(mut seq: impl Iterator<Item = u8>, base: u8) -> Option<Range<usize>> {
+    let start = seq.by_ref().take(END_TOLERANCE + 1).position(|other| other.to_ascii_uppercase() == base)?;
+    let mut end = start + 1;
+    let mut score = 1;
+    let mut best_score = 1;
+
+    for (i, other) in seq.enumerate().map(|(i, other)| (i + start + 1, other)) {
+        if other.to_ascii_uppercase() == base {
+            score += 1;
+            if score > best_score {
+                best_score = score;
+                end = i + 1;
+            }
+        } else {
+            score -= MISMATCH_PENALTY;
+            if score <= 0 {
+                break;
+            }
+        }
+    }
+    Some(start..end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tails(seq: &[u8]) -> Vec<Tail> {
+        let fasta = Fasta::new("read".to_owned(), seq.to_vec().into(), None);
+        let cmds = TailConfig { poly_a: Some(10), poly_t: Some(10), poly_g: None, window: 100 };
+        let mut output = Vec::new();
+        find_tails(&fasta, &cmds, &mut output);
+        output
+    }
+
+    #[test]
+    fn finds_tails_at_read_ends() {
+        let seq = [b"TTTTTTTTTTTT".as_slice(), b"GCATCGGATCCGTAGCAT", b"AAAAAAAACAAAAAAAAA", b"C"].concat();
+        let found = tails(&seq);
+
+        assert_eq!(found.len(), 2);
+        let poly_a = found.iter().find(|tail| matches!(tail.get_tail_type(), TailType::PolyA)).unwrap();
+        assert_eq!((poly_a.get_start(), poly_a.get_end()), (30, 48));
+        let poly_t = found.iter().find(|tail| matches!(tail.get_tail_type(), TailType::PolyT)).unwrap();
+        assert_eq!((poly_t.get_start(), poly_t.get_end()), (0, 12));
+    }
+
+    #[test]
+    fn finds_lowercase_poly_g_within_the_window() {
+        let fasta = Fasta::new("read".to_owned(), [b"TTTTTTTTCATCGGATCC".as_slice(), b"ggggggggggggCA"].concat().into(), None);
+        let mut cmds = TailConfig { poly_a: Some(1), poly_t: Some(9), poly_g: Some(12), window: 100 };
+        let mut output = Vec::new();
+        find_tails(&fasta, &cmds, &mut output);
+
+        //The poly-T is shorter than its minimum, and the poly-G can start after the last two bases
+        assert_eq!(output.len(), 2);
+        assert_eq!((output[0].get_tail_type().to_string(), output[0].get_start(), output[0].get_end()), ("poly-A".to_owned(), 31, 32));
+        assert_eq!((output[1].get_tail_type().to_string(), output[1].get_start(), output[1].get_end()), ("poly-G".to_owned(), 18, 30));
+        assert_eq!(output[1].get_length(), 12);
+
+        //Only the part of the tail inside the window is counted
+        cmds.window = 10;
+        output.clear();
+        find_tails(&fasta, &cmds, &mut output);
+        assert!(output.iter().all(|tail| !matches!(tail.get_tail_type(), TailType::PolyG)));
+    }
+
+    #[test]
+    fn ignores_internal_runs() {
+        let seq = [b"GCATCGGATCCG".as_slice(), b"AAAAAAAAAAAAAAAAAAAA", b"TTTTTTTTTTTTTTTTTTTT", b"GCATCGGATCCGTAGC"].concat();
+        assert!(tails(&seq).is_empty());
+    }
+}
@@ -1,111 +1,165 @@
 use std::{
-    cmp::{max, min},
+    cmp::max,
     mem,
 };
 
 use crate::{
-    command_line::WfaArgs,
-    fasta_parsing::Fasta,
-    output::PalindromeData,
+    config::{MirrorConfig, WfaConfig},
+    fasta_parsing::{reverse_complement, Fasta, Sequence},
+    finder::PalindromeFinder,
+    output::{PalindromeData, RepeatType, Strand},
+    packed::PackedSeq,
 };
 
-use anyhow::{bail, ensure, Ok, Result};
+use anyhow::{ensure, Ok, Result};
 
 const SIZE: usize = 1000;
 
 //Use WFA algorithm to find palindromes
-pub fn wfa_palins(
-    fasta: Fasta,
-    output: &mut Vec<PalindromeData>,
-    wfa_args: &WfaArgs,
-) -> Result<()> {
-
-    //Converts the sequence to bytes, where A = !T, C = !G
-    let mut seq_clone = fasta.sequence.clone();
-    let bytes_seq = unsafe { seq_clone.as_bytes_mut() };
-    sequence_to_bytes(bytes_seq)?;
+impl PalindromeFinder for WfaConfig {
+    type Scratch = Waves;
 
-    let seq = fasta.sequence;
+    fn scratch(&self) -> Result<Waves> {
+        Ok(Waves::new(self.gap_len))
+    }
 
-    let len = seq.len();
-    let mut index = 0;
+    fn find(&self, fasta: &Fasta, output: &mut Vec<PalindromeData>, waves: &mut Waves) -> Result<()> {
+        search_strands::<true>(fasta, output, self, waves)
+    }
+}
 
-    let mut wf = vec![0; max(SIZE, wfa_args.gap_len + 2)];
-    let mut wf_next = vec![0; max(SIZE, wfa_args.gap_len + 2)];
+//Use WFA algorithm to find mirror repeats, where the second arm is the reverse of the first
+impl PalindromeFinder for MirrorConfig {
+    type Scratch = Waves;
 
-    let first_wave = vec![0; wfa_args.gap_len + 2];
+    fn scratch(&self) -> Result<Waves> {
+        if let Some(bias) = self.purine_bias {
+            ensure!((0.0..=1.0).contains(&bias), "Purine bias must be between 0 and 1");
+        }
+        self.wfa.scratch()
+    }
 
-    while index <= len {
-        let mut edit_dist = 0;
-        let mut wf_len = wfa_args.gap_len + 1;
+    fn find(&self, fasta: &Fasta, output: &mut Vec<PalindromeData>, waves: &mut Waves) -> Result<()> {
+        let first = output.len();
+        wfa_mirrors(fasta, output, &self.wfa, waves)?;
 
-        let mut max_index = 0;
-        let mut max_score = 0.0;
+        if let Some(bias) = self.purine_bias {
+            let mut mirrors = output.split_off(first);
+            mirrors.retain(|mirror| purine_bias(mirror.get_sequence()) >= bias);
+            output.append(&mut mirrors);
+        }
+        Ok(())
+    }
+}
 
-        //Reset first wave to 0s
-        wf[..=wf_len].copy_from_slice(&first_wave);
+pub(crate) fn wfa_mirrors(
+    fasta: &Fasta,
+    output: &mut Vec<PalindromeData>,
+    wfa_args: &WfaConfig,
+    waves: &mut Waves,
+) -> Result<()> {
+    let first = output.len();
+    search_strands::<false>(fasta, output, wfa_args, waves)?;
+    for mirror in &mut output[first..] {
+        mirror.set_repeat_type(RepeatType::Mirror);
+    }
+    Ok(())
+}
 
-        'outer: while (edit_dist as f32) / (wf[max_index] as f32 + 0.001)
-            <= wfa_args.mismatch_proportion
-        {
-            let mut max_wf_score = 0.0;
-            for i in 0..wf_len {
+//Highest proportion of either purines or pyrimidines in a sequence
+fn purine_bias(seq: &[u8]) -> f32 {
+    let purines = seq.iter().filter(|b| matches!(b, b'A' | b'G' | b'a' | b'g')).count();
+    let pyrimidines = seq.iter().filter(|b| matches!(b, b'C' | b'T' | b'c' | b't')).count();
+    max(purines, pyrimidines) as f32 / seq.len() as f32
+}
 
-                //Extend wave along the matches
-                let (mut x, mut y) = get_xy(wf_len, i, wf[i], wfa_args.gap_len);
-                x += index;
-                let counter = extend_wave(x, y, index, bytes_seq)?; //Using bytes_seq for fast complement checks
+//COMPLEMENT decides whether the arms are matched as complements (palindromes) or as identical bases (mirror repeats)
+fn search_strands<const COMPLEMENT: bool>(
+    fasta: &Fasta,
+    output: &mut Vec<PalindromeData>,
+    wfa_args: &WfaConfig,
+    waves: &mut Waves,
+) -> Result<()> {
+    if !wfa_args.both_strands {
+        return find_palins::<COMPLEMENT>(fasta.get_sequence(), fasta.get_id(), output, wfa_args, waves);
+    }
 
-                wf[i] += counter as usize;
-                x += counter as usize;
-                y += counter as usize;
+    let mut forward = Vec::new();
+    find_palins::<COMPLEMENT>(fasta.get_sequence(), fasta.get_id(), &mut forward, wfa_args, waves)?;
 
-                let score = calculate_score(x, y, edit_dist, wfa_args);
-                max_wf_score = f32::max(max_wf_score, score);
+    let mut reverse = Vec::new();
+    let reverse_seq = Sequence::from(reverse_complement(&fasta.get_sequence().to_contiguous())?);
+    find_palins::<COMPLEMENT>(&reverse_seq, fasta.get_id(), &mut reverse, wfa_args, waves)?;
+    let reverse = reverse
+        .into_iter()
+        .map(|palin| palin.into_forward_strand(fasta.get_sequence()));
 
-                if wf[i] > wf[max_index] {
-                    max_index = i;
-                }
+    merge_strands(forward, reverse, output);
+    Ok(())
+}
 
-                if x == len || y == index {
-                    break 'outer;
+//Merges palindromes from both strands, keeping the longest of any overlapping pair
+fn merge_strands(
+    forward: Vec<PalindromeData>,
+    reverse: impl Iterator<Item = PalindromeData>,
+    output: &mut Vec<PalindromeData>,
+) {
+    let mut palins: Vec<PalindromeData> = forward.into_iter().chain(reverse).collect();
+    palins.sort_by_key(|palin| (palin.get_start(), palin.get_end()));
+
+    let first = output.len();
+    for palin in palins {
+        if output.len() > first {
+            let last = output.last_mut().unwrap();
+            if palin.get_start() <= last.get_end() {
+                if palin.get_start() == last.get_start() && palin.get_end() == last.get_end() {
+                    last.set_strand(Strand::Both);
+                } else if palin.get_length() > last.get_length()
+                    || (palin.get_length() == last.get_length() && palin.get_mismatches() < last.get_mismatches())
+                {
+                    *last = palin;
                 }
+                continue;
             }
+        }
+        output.push(palin);
+    }
+}
 
-            max_score = f32::max(max_score, max_wf_score);
+fn find_palins<const COMPLEMENT: bool>(
+    seq: &Sequence,
+    name: &str,
+    output: &mut Vec<PalindromeData>,
+    wfa_args: &WfaConfig,
+    waves: &mut Waves,
+) -> Result<()> {
 
-            //X-drop pruning
-            if max_wf_score < max_score - f32::max(wfa_args.x_drop, (wf[max_index] as f32) * 0.1) {
-                break;
-            }
+    //Packs the sequence into 2 bits per base for fast complement checks
+    let packed_seq = PackedSeq::new(seq)?;
 
-            next_wave(&mut wf, &mut wf_next, wf_len);
-            max_index += 1;
-            edit_dist += 1;
-            wf_len += 2;
-        }
+    let len = seq.len();
+    let mut index = 0;
 
-        if wf[max_index] == 0 {
+    while index <= len {
+        let Some(arms) = align_arms::<COMPLEMENT>(&packed_seq, index, index, waves, wfa_args)? else {
             index += 1;
             continue;
-        }
+        };
 
         let mut increment = 1;
 
-        let (x, y) = get_xy(wf_len, max_index, wf[max_index], wfa_args.gap_len);
-        let palin = &seq[index - y..index + x];
-        let gap = y - wf[max_index];
+        let (x, y) = (arms.right_len, arms.left_len);
 
         if x >= wfa_args.min_length {
             let palin = PalindromeData::new(
                 (index - y) as u32,
                 (index + x - 1) as u32,
                 x as u32,
-                gap as u32,
+                arms.gap as u32,
                 (x + y) as u32,
-                edit_dist,
-                fasta.name.to_owned(),
-                palin.to_owned(),
+                arms.edit_dist,
+                name.to_owned(),
+                seq.slice(index - y..index + x).into_owned(),
             );
             output.push(palin);
             increment = x;
@@ -115,60 +169,111 @@ pub fn wfa_palins(
     Ok(())
 }
 
-fn calculate_score(x: usize, y: usize, d: u32, args: &WfaArgs) -> f32 {
-    (x + y) as f32 * (args.match_bonus / 2.0)
-        - (d as f32) * (args.match_bonus - (-args.mismatch_penalty))
+//Wave buffers, kept between alignments and records to avoid reallocating
+pub struct Waves {
+    wf: Vec<usize>,
+    wf_next: Vec<usize>,
+    first_wave: Vec<usize>,
 }
 
-fn sequence_to_bytes(seq: &mut [u8]) -> Result<()> {
-    for i in seq.iter_mut() {
-        *i = match i {
-            65 | 97 => 2,    // A, a
-            84 | 116 => 253, // T, t
-            67 | 99 => 3,    // C, c
-            71 | 103 => 252, // G, g
-            78 | 110 => 1, // N, n
-            _ => bail!("Not a base pair - check format"),
-        };
+impl Waves {
+    pub(crate) fn new(gap_len: usize) -> Self {
+        Self {
+            wf: vec![0; max(SIZE, gap_len + 2)],
+            wf_next: vec![0; max(SIZE, gap_len + 2)],
+            first_wave: vec![0; gap_len + 2],
+        }
     }
-    Ok(())
 }
 
-//Extends wave across matches
-fn extend_wave(mut x: usize, mut y: usize, index: usize, seq: &[u8]) -> Result<u32> {
-    let len = seq.len();
-    let mut count = 8;
-    let mut counter = 0;
-
-    while x < len && y < index && count >= 8 {
-        let len1 = min(len - x, 8);
-        let len2 = min(index - y, 8);
-        count = count_matching(&seq[x..x + len1], &seq[index - y - len2..index - y])?;
-        x += count as usize;
-        y += count as usize;
-        counter += count;
-    }
-    Ok(counter)
+//Lengths of the arms found by align_arms, where the left length includes the gap
+pub(crate) struct ArmAlignment {
+    pub(crate) right_len: usize,
+    pub(crate) left_len: usize,
+    pub(crate) gap: usize,
+    pub(crate) edit_dist: u32,
 }
 
-//Counts the matching sequences with bit manipulation
-fn count_matching(seq1: &[u8], seq2: &[u8]) -> Result<u32> {
-    ensure!(
-        seq1.len() <= 8,
-        "Sequence length too long when processing bits"
-    );
-    ensure!(
-        seq2.len() <= 8,
-        "Sequence length too long when processing bits"
-    );
-    let mut buf1 = [0; 8];
-    let mut buf2 = [0; 8];
-    buf1[..seq1.len()].copy_from_slice(seq1);
-    buf2[8 - seq2.len()..].copy_from_slice(seq2);
-    let num1 = u64::from_le_bytes(buf1);
-    let num2 = !u64::from_be_bytes(buf2);
-    let diff = num1 ^ num2;
-    Ok(diff.trailing_zeros() / 8)
+//Aligns the arms outwards, with the left arm ending before left_end and the right arm starting at right_start
+pub(crate) fn align_arms<const COMPLEMENT: bool>(
+    packed_seq: &PackedSeq,
+    left_end: usize,
+    right_start: usize,
+    waves: &mut Waves,
+    wfa_args: &WfaConfig,
+) -> Result<Option<ArmAlignment>> {
+    let len = packed_seq.len();
+    let Waves { wf, wf_next, first_wave } = waves;
+
+    let mut edit_dist = 0;
+    let mut wf_len = wfa_args.gap_len + 1;
+
+    let mut max_index = 0;
+    let mut max_score = 0.0;
+
+    //Reset first wave to 0s
+    wf[..=wf_len].copy_from_slice(first_wave);
+
+    'outer: while (edit_dist as f32) / (wf[max_index] as f32 + 0.001)
+        <= wfa_args.mismatch_proportion
+    {
+        let mut max_wf_score = 0.0;
+        for i in 0..wf_len {
+
+            //Extend wave along the matches
+            let (mut x, mut y) = get_xy(wf_len, i, wf[i], wfa_args.gap_len);
+            x += right_start;
+            let counter = if y < left_end {
+                packed_seq.extend::<COMPLEMENT>(x, left_end - y)
+            } else {
+                0
+            };
+
+            wf[i] += counter;
+            x += counter;
+            y += counter;
+
+            let score = calculate_score(x, y, edit_dist, wfa_args);
+            max_wf_score = f32::max(max_wf_score, score);
+
+            if wf[i] > wf[max_index] {
+                max_index = i;
+            }
+
+            if x == len || y == left_end {
+                break 'outer;
+            }
+        }
+
+        max_score = f32::max(max_score, max_wf_score);
+
+        //X-drop pruning
+        if max_wf_score < max_score - f32::max(wfa_args.x_drop, (wf[max_index] as f32) * 0.1) {
+            break;
+        }
+
+        next_wave(wf, wf_next, wf_len);
+        max_index += 1;
+        edit_dist += 1;
+        wf_len += 2;
+    }
+
+    if wf[max_index] == 0 {
+        return Ok(None);
+    }
+
+    let (x, y) = get_xy(wf_len, max_index, wf[max_index], wfa_args.gap_len);
+    Ok(Some(ArmAlignment {
+        right_len: x,
+        left_len: y,
+        gap: y - wf[max_index],
+        edit_dist,
+    }))
+}
+
+pub(crate) fn calculate_score(x: usize, y: usize, d: u32, args: &WfaConfig) -> f32 {
+    (x + y) as f32 * (args.match_bonus / 2.0)
+        - (d as f32) * (args.match_bonus - (-args.mismatch_penalty))
 }
 
 //Branches out to next wave
@@ -207,4 +312,134 @@ fn get_xy(wf_len: usize, index: usize, length: usize, gap_size: usize) -> (usize
     } else {
         (length + offset.unsigned_abs() as usize, length)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{find_palindromes, search};
+
+    //Bases from a fixed LCG, so tests are repeatable
+    fn random_seq(len: usize, seed: u32) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                b"ACGT"[(state >> 16) as usize % 4]
+            })
+            .collect()
+    }
+
+    fn revcomp(seq: &[u8]) -> Vec<u8> {
+        reverse_complement(seq).unwrap()
+    }
+
+    fn search_seq(seq: &[u8], config: &WfaConfig) -> Vec<PalindromeData> {
+        let fasta = Fasta::new("seq".to_owned(), seq.to_vec().into(), None);
+        let mut palins = Vec::new();
+        search(&fasta, config, &mut palins).unwrap();
+        palins
+    }
+
+    #[test]
+    fn symmetric_palindromes_are_on_both_strands() {
+        let arm = random_seq(30, 3);
+        let seq = [random_seq(50, 1), arm.clone(), b"GAT".to_vec(), revcomp(&arm), random_seq(50, 2)].concat();
+        let config = WfaConfig { min_length: 20, gap_len: 5, both_strands: true, ..Default::default() };
+
+        let palins = search_seq(&seq, &config);
+        assert_eq!(palins.len(), 1);
+        let palin = &palins[0];
+        assert!(palin.get_start() <= 50 && palin.get_end() >= 112);
+        assert_eq!(palin.get_strand(), Strand::Both);
+        assert_eq!(palin.to_string().split('\t').nth(6), Some("."));
+        assert_eq!(palin.get_sequence(), &seq[palin.get_start() as usize..=palin.get_end() as usize]);
+    }
+
+    #[test]
+    fn merged_strands_match_separate_searches() {
+        let seq = random_seq(5000, 1);
+        let config = WfaConfig { min_length: 6, mismatch_proportion: 0.3, x_drop: 5.0, both_strands: true, ..Default::default() };
+        let single = WfaConfig { both_strands: false, ..config.clone() };
+        let forward = search_seq(&seq, &single);
+        let reverse = search_seq(&revcomp(&seq), &single);
+
+        let palins = search_seq(&seq, &config);
+        let len = seq.len() as u32;
+        for palin in &palins {
+            let (start, end) = (palin.get_start(), palin.get_end());
+            assert_eq!(palin.get_sequence(), &seq[start as usize..=end as usize]);
+            let on_forward = forward.iter().any(|other| (other.get_start(), other.get_end()) == (start, end));
+            let on_reverse = reverse.iter().find(|other| (len - 1 - other.get_end(), len - 1 - other.get_start()) == (start, end));
+            let strand = match (on_forward, on_reverse) {
+                (true, Some(_)) => Strand::Both,
+                (true, None) => Strand::Forward,
+                (false, Some(other)) => {
+                    //The right arm on the reverse strand is the left arm on the forward strand
+                    assert_eq!(palin.get_arm_length(), other.get_length() - other.get_arm_length() - other.get_gap());
+                    Strand::Reverse
+                }
+                (false, None) => panic!("{start}..{end} wasn't found on either strand"),
+            };
+            assert_eq!(palin.get_strand(), strand);
+            assert_eq!(palin.to_string().split('\t').nth(6), Some(strand.to_string().as_str()));
+        }
+
+        //Greedy skipping along each strand finds some palindromes on only one of them
+        for strand in [Strand::Forward, Strand::Reverse, Strand::Both] {
+            assert!(palins.iter().any(|palin| palin.get_strand() == strand), "No palindromes on {strand}");
+        }
+    }
+
+    #[test]
+    fn finds_mirror_repeats() {
+        let arm = random_seq(30, 5);
+        let reversed: Vec<u8> = arm.iter().rev().copied().collect();
+        let seq = [random_seq(40, 6), arm, reversed, random_seq(40, 7)].concat();
+        let mut config = MirrorConfig { wfa: WfaConfig { min_length: 20, ..Default::default() }, purine_bias: None };
+
+        let mirrors: Vec<_> = find_palindromes(&seq, &config).unwrap().collect();
+        assert_eq!(mirrors.len(), 1);
+        let mirror = &mirrors[0];
+        assert_eq!(mirror.get_repeat_type(), RepeatType::Mirror);
+        assert!(mirror.get_start() <= 40 && mirror.get_end() >= 99);
+        assert_eq!(mirror.get_gap(), 0);
+
+        //Random arms are far from all purines or all pyrimidines
+        config.purine_bias = Some(0.95);
+        assert_eq!(find_palindromes(&seq, &config).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn rejects_purine_bias_out_of_range() {
+        for bias in [-0.1, 1.5, f32::NAN] {
+            let config = MirrorConfig { wfa: WfaConfig::default(), purine_bias: Some(bias) };
+            assert!(config.scratch().is_err());
+        }
+        let config = MirrorConfig { wfa: WfaConfig::default(), purine_bias: Some(1.0) };
+        assert!(config.scratch().is_ok());
+    }
+
+    #[test]
+    fn masked_bases_count_as_mismatches() {
+        let arm = random_seq(40, 11);
+        let seq = [b"GGGGGGGGGG".as_slice(), &arm, &revcomp(&arm), b"GGGGGGGGGG"].concat();
+        let config = WfaConfig { min_length: 20, ..Default::default() };
+        let unmasked = find_palindromes(&seq, &config).unwrap().next().unwrap();
+        assert!(unmasked.get_start() <= 15);
+
+        let mut quality = vec![b'I'; seq.len()];
+        quality[15] = b'#';
+        let mut fasta = Fasta::new("read".to_owned(), seq.into(), Some(quality));
+        fasta.mask_low_quality(10);
+        let mut palins = Vec::new();
+        search(&fasta, &config, &mut palins).unwrap();
+
+        //The arm still extends over the masked base, counting it as a mismatch
+        assert_eq!(palins.len(), 1);
+        let masked = &palins[0];
+        assert!(masked.get_start() <= 15);
+        assert!(masked.get_mismatches() >= 1);
+        assert_eq!(masked.get_sequence()[15 - masked.get_start() as usize], b'N');
+    }
+}